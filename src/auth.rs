@@ -0,0 +1,400 @@
+use crate::SharedState;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// --- Configuration Constants ---
+const QUOTA_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+const KEY_HASH_LENGTH: usize = 32; // SHA-256 of the key
+
+// --- Key File Format ---
+// The key file is JSON and holds only hashes, so it can be readable by the
+// server without exposing the keys themselves:
+//
+//   {"keys": [{"label": "ci", "sha256": "<hex SHA-256 of the key>",
+//              "max_pastes_per_day": 500, "max_bytes_per_day": 1073741824}]}
+//
+// Both quotas are optional; a key without them is unlimited.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    label: String,
+    sha256: String,
+    #[serde(default)]
+    max_pastes_per_day: Option<u32>,
+    #[serde(default)]
+    max_bytes_per_day: Option<u64>,
+}
+
+// --- Data Structures ---
+/// Per-day limits of one key; `None` means unlimited.
+#[derive(Clone, Copy, Debug)]
+struct Quota {
+    max_pastes: Option<u32>,
+    max_bytes: Option<u64>,
+}
+
+/// What a key has created in the current quota period.
+struct Usage {
+    period_start: Instant,
+    pastes: u32,
+    bytes: u64,
+}
+
+/// One API key, identified in logs by its label. Usage is kept in memory and
+/// starts over after a restart.
+pub struct ApiKey {
+    pub label: String,
+    quota: Quota,
+    usage: Mutex<Usage>,
+}
+
+impl ApiKey {
+    /// Charges one paste of `size` bytes against the key's quota, or returns
+    /// how long until the quota period restarts.
+    pub fn reserve(&self, size: usize) -> Result<(), Duration> {
+        let mut usage = self.usage.lock().expect("API key lock poisoned");
+        if usage.period_start.elapsed() >= QUOTA_PERIOD {
+            *usage = Usage {
+                period_start: Instant::now(),
+                pastes: 0,
+                bytes: 0,
+            };
+        }
+        let pastes = usage.pastes.saturating_add(1);
+        let bytes = usage.bytes.saturating_add(size as u64);
+        let over_pastes = self.quota.max_pastes.is_some_and(|max| pastes > max);
+        let over_bytes = self.quota.max_bytes.is_some_and(|max| bytes > max);
+        if over_pastes || over_bytes {
+            return Err(QUOTA_PERIOD.saturating_sub(usage.period_start.elapsed()));
+        }
+        usage.pastes = pastes;
+        usage.bytes = bytes;
+        Ok(())
+    }
+
+    /// Gives back a reservation whose paste could not be stored.
+    pub fn release(&self, size: usize) {
+        let mut usage = self.usage.lock().expect("API key lock poisoned");
+        usage.pastes = usage.pastes.saturating_sub(1);
+        usage.bytes = usage.bytes.saturating_sub(size as u64);
+    }
+}
+
+/// The keys allowed to create pastes, looked up by the SHA-256 of the key.
+/// Keys are random secrets, so a plain hash is enough to keep the file safe.
+pub struct ApiKeys {
+    keys: HashMap<[u8; KEY_HASH_LENGTH], Arc<ApiKey>>,
+}
+
+#[derive(Debug)]
+pub enum KeyFileError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "{}", e),
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl ApiKeys {
+    pub fn load(path: &Path) -> Result<Self, KeyFileError> {
+        let contents = std::fs::read_to_string(path).map_err(KeyFileError::Read)?;
+        let file: KeyFile = serde_json::from_str(&contents).map_err(KeyFileError::Parse)?;
+        if file.keys.is_empty() {
+            return Err(KeyFileError::Invalid("No keys configured".to_string()));
+        }
+        let mut labels = HashSet::new();
+        let mut keys = HashMap::new();
+        for entry in file.keys {
+            if entry.label.is_empty() || !labels.insert(entry.label.clone()) {
+                return Err(KeyFileError::Invalid(format!("Key labels must be unique and non-empty: {:?}", entry.label)));
+            }
+            let Some(hash) = decode_hex_hash(&entry.sha256) else {
+                return Err(KeyFileError::Invalid(format!(
+                    "Key {:?} needs a sha256 of 64 hex digits",
+                    entry.label
+                )));
+            };
+            let key = ApiKey {
+                label: entry.label,
+                quota: Quota {
+                    max_pastes: entry.max_pastes_per_day,
+                    max_bytes: entry.max_bytes_per_day,
+                },
+                usage: Mutex::new(Usage {
+                    period_start: Instant::now(),
+                    pastes: 0,
+                    bytes: 0,
+                }),
+            };
+            if keys.insert(hash, Arc::new(key)).is_some() {
+                return Err(KeyFileError::Invalid("The same key hash is listed twice".to_string()));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn find(&self, key: &str) -> Option<Arc<ApiKey>> {
+        let hash: [u8; KEY_HASH_LENGTH] = Sha256::digest(key.as_bytes()).into();
+        self.keys.get(&hash).cloned()
+    }
+}
+
+fn decode_hex_hash(hex: &str) -> Option<[u8; KEY_HASH_LENGTH]> {
+    let hex = hex.as_bytes();
+    // `from_str_radix` alone would also accept a sign, as in "+f".
+    if hex.len() != KEY_HASH_LENGTH * 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut hash = [0u8; KEY_HASH_LENGTH];
+    for (byte, pair) in hash.iter_mut().zip(hex.chunks_exact(2)) {
+        let digits = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+    Some(hash)
+}
+
+// --- Middleware ---
+/// Requires `Authorization: Bearer <key>` on write endpoints when API keys are
+/// configured, and hands the key to the handler for quota accounting.
+pub async fn require_api_key(State(state): State<SharedState>, mut request: Request, next: Next) -> Response {
+    let Some(api_keys) = &state.api_keys else {
+        return next.run(request).await;
+    };
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, key)| key.trim());
+    let Some(key) = bearer.and_then(|key| api_keys.find(key)) else {
+        warn!("Rejected write request without a valid API key");
+        let mut response = (StatusCode::UNAUTHORIZED, "A valid API key is required").into_response();
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    };
+    request.extensions_mut().insert(key);
+    next.run(request).await
+}
+
+pub fn quota_exceeded(key: &ApiKey, retry_after: Duration) -> (StatusCode, String) {
+    warn!("API key {:?} exceeded its daily quota", key.label);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        format!("Daily quota for this API key is used up. It resets in {} seconds.", retry_after.as_secs().max(1)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_state;
+    use axum::{middleware, routing::get, Extension, Router};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn sha256_hex(key: &str) -> String {
+        Sha256::digest(key.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Writes `contents` to a key file named after the test and loads it.
+    fn load_key_file(name: &str, contents: &str) -> Result<ApiKeys, KeyFileError> {
+        let path = std::env::temp_dir().join(format!("rsdrop-keys-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let result = ApiKeys::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn key_with_quota(max_pastes: Option<u32>, max_bytes: Option<u64>) -> ApiKey {
+        ApiKey {
+            label: "test".to_string(),
+            quota: Quota { max_pastes, max_bytes },
+            usage: Mutex::new(Usage {
+                period_start: Instant::now(),
+                pastes: 0,
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Pretends `elapsed` has passed since the key's quota period started.
+    fn age(key: &ApiKey, elapsed: Duration) {
+        key.usage.lock().unwrap().period_start -= elapsed;
+    }
+
+    #[test]
+    fn key_file_loads_hashed_keys() {
+        let contents = format!(
+            r#"{{"keys": [{{"label": "ci", "sha256": "{}", "max_pastes_per_day": 5}},
+                          {{"label": "ops", "sha256": "{}"}}]}}"#,
+            sha256_hex("ci-secret"),
+            sha256_hex("ops-secret").to_uppercase()
+        );
+        let keys = load_key_file("valid", &contents).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys.find("ci-secret").unwrap().label, "ci");
+        assert_eq!(keys.find("ops-secret").unwrap().label, "ops");
+        assert!(keys.find("ci-secret ").is_none());
+        assert!(keys.find(&sha256_hex("ci-secret")).is_none(), "the hash itself is not a key");
+    }
+
+    #[test]
+    fn key_file_rejects_bad_hashes() {
+        let valid = sha256_hex("secret");
+        let non_hex = "zz".repeat(KEY_HASH_LENGTH);
+        let signed = format!("+{}", &valid[1..]);
+        let long = format!("{}0", valid);
+        for (name, hash) in [("short", &valid[1..]), ("long", &long), ("non-hex", &non_hex), ("signed", &signed)] {
+            let contents = format!(r#"{{"keys": [{{"label": "ci", "sha256": "{}"}}]}}"#, hash);
+            let error = load_key_file(name, &contents).err().unwrap();
+            assert!(matches!(error, KeyFileError::Invalid(_)), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn key_file_rejects_duplicates() {
+        let hash = sha256_hex("secret");
+        let same_hash = format!(
+            r#"{{"keys": [{{"label": "a", "sha256": "{0}"}}, {{"label": "b", "sha256": "{0}"}}]}}"#,
+            hash
+        );
+        let error = load_key_file("duplicate-hash", &same_hash).err().unwrap();
+        assert!(error.to_string().contains("listed twice"), "{}", error);
+
+        let same_label = format!(
+            r#"{{"keys": [{{"label": "a", "sha256": "{}"}}, {{"label": "a", "sha256": "{}"}}]}}"#,
+            hash,
+            sha256_hex("other")
+        );
+        let error = load_key_file("duplicate-label", &same_label).err().unwrap();
+        assert!(error.to_string().contains("unique"), "{}", error);
+    }
+
+    #[test]
+    fn key_file_rejects_unknown_fields_and_empty_lists() {
+        let hash = sha256_hex("secret");
+        let unknown_entry_field = format!(r#"{{"keys": [{{"label": "a", "sha256": "{}", "key": "secret"}}]}}"#, hash);
+        let error = load_key_file("unknown-entry-field", &unknown_entry_field).err().unwrap();
+        assert!(matches!(error, KeyFileError::Parse(_)), "{}", error);
+
+        let unknown_top_field = format!(r#"{{"keys": [{{"label": "a", "sha256": "{}"}}], "quota": 1}}"#, hash);
+        let error = load_key_file("unknown-top-field", &unknown_top_field).err().unwrap();
+        assert!(matches!(error, KeyFileError::Parse(_)), "{}", error);
+
+        let error = load_key_file("empty", r#"{"keys": []}"#).err().unwrap();
+        assert!(matches!(error, KeyFileError::Invalid(_)), "{}", error);
+    }
+
+    #[test]
+    fn quota_counts_pastes_and_bytes() {
+        let key = key_with_quota(Some(2), Some(100));
+        assert_eq!(key.reserve(40), Ok(()));
+        let retry_after = key.reserve(70).unwrap_err();
+        assert!(retry_after <= QUOTA_PERIOD && retry_after > QUOTA_PERIOD - Duration::from_secs(60));
+        assert_eq!(key.reserve(60), Ok(()));
+        assert!(key.reserve(0).is_err(), "paste limit reached");
+
+        // A paste that could not be stored gives its share back.
+        key.release(60);
+        assert_eq!(key.reserve(10), Ok(()));
+        assert!(key.reserve(0).is_err());
+
+        let unlimited = key_with_quota(None, None);
+        for _ in 0..1000 {
+            assert_eq!(unlimited.reserve(usize::MAX), Ok(()));
+        }
+    }
+
+    #[test]
+    fn quota_resets_after_a_day() {
+        let key = key_with_quota(Some(1), None);
+        assert_eq!(key.reserve(1), Ok(()));
+        age(&key, QUOTA_PERIOD - Duration::from_secs(10));
+        let retry_after = key.reserve(1).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(10));
+
+        age(&key, Duration::from_secs(10));
+        assert_eq!(key.reserve(1), Ok(()));
+        assert!(key.reserve(1).is_err(), "the new period starts from zero");
+    }
+
+    /// Sends a GET with the given `Authorization` header to a router behind the
+    /// middleware and returns the status code and body.
+    async fn request_with(state: SharedState, authorization: Option<&str>) -> (u16, String) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|key: Option<Extension<Arc<ApiKey>>>| async move {
+                    key.map_or_else(|| "anonymous".to_string(), |Extension(key)| key.label.clone())
+                }),
+            )
+            .route_layer(middleware::from_fn_with_state(state, require_api_key));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let authorization = authorization.map_or_else(String::new, |value| format!("Authorization: {}\r\n", value));
+        let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n", authorization);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        if status == 401 {
+            assert!(response.contains("www-authenticate: Bearer"), "{}", response);
+        }
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn middleware_requires_a_known_bearer_key() {
+        let path = std::env::temp_dir().join(format!("rsdrop-keys-{}-middleware.json", std::process::id()));
+        let contents = format!(r#"{{"keys": [{{"label": "ci", "sha256": "{}"}}]}}"#, sha256_hex("secret"));
+        std::fs::write(&path, contents).unwrap();
+        let state = test_state(&["--api-keys", path.to_str().unwrap()]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(request_with(state.clone(), None).await.0, 401);
+        assert_eq!(request_with(state.clone(), Some("Bearer wrong")).await.0, 401);
+        assert_eq!(request_with(state.clone(), Some("Basic secret")).await.0, 401);
+        assert_eq!(request_with(state.clone(), Some("secret")).await.0, 401);
+        assert_eq!(request_with(state.clone(), Some("Bearer secret")).await, (200, "ci".to_string()));
+        assert_eq!(request_with(state, Some("bearer  secret")).await, (200, "ci".to_string()));
+
+        let open = test_state(&[]);
+        assert_eq!(request_with(open, None).await, (200, "anonymous".to_string()));
+    }
+}
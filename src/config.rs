@@ -0,0 +1,177 @@
+use clap::{error::ErrorKind, parser::ValueSource, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// --- Configuration Constants ---
+const ENV_PREFIX: &str = "RSDROP_";
+const CONFIG_ARG: &str = "config"; // Long name of the option that points at the file
+
+// --- Layering ---
+// Every option can be set in four places. From highest to lowest precedence:
+// the command line, an RSDROP_* environment variable named after the long
+// option (`--max-upload-bytes` is RSDROP_MAX_UPLOAD_BYTES), the TOML file
+// given by `--config`, and the built-in default. Settings from the file that
+// the command line and environment leave unset are appended to the arguments,
+// so they go through the same parsers, range checks and `requires` /
+// `required_if` rules as values typed on the command line.
+//
+//   max_paste_bytes = 1048576
+//   store = "sqlite"
+//   data_dir = "/var/lib/rsdrop"
+//   trusted_proxy = ["10.0.0.0/8"]
+
+/// Parses `T` from all configuration sources, exiting with a usage error when
+/// any of them holds an unknown setting or an invalid value.
+pub fn parse<T: CommandFactory + FromArgMatches>() -> T {
+    let command = T::command().mut_args(|arg| match arg.get_long() {
+        Some(long) => {
+            let name = format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase());
+            arg.env(name)
+        }
+        None => arg,
+    });
+    parse_from(command, std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+fn parse_from<T: FromArgMatches>(
+    mut command: Command,
+    args: impl IntoIterator<Item = impl Into<OsString>>,
+) -> Result<T, clap::Error> {
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    // A first, lenient pass finds the file and the options already set on
+    // the command line or in the environment; the real parse follows once
+    // the file's settings are appended.
+    let explicit = command.clone().ignore_errors(true).try_get_matches_from(&args)?;
+    if let Some(path) = explicit.get_one::<PathBuf>(CONFIG_ARG) {
+        let file_args = read_file(path).and_then(|settings| file_args(&command, &explicit, settings));
+        match file_args {
+            Ok(file_args) => args.extend(file_args),
+            Err(e) => return Err(command.error(ErrorKind::InvalidValue, format!("Config file {:?}: {}", path, e))),
+        }
+    }
+    let matches = command.try_get_matches_from_mut(args)?;
+    T::from_arg_matches(&matches).map_err(|e| e.format(&mut command))
+}
+
+fn read_file(path: &Path) -> Result<toml::Table, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    contents.parse::<toml::Table>().map_err(|e| e.to_string())
+}
+
+/// Turns each setting of the file into `--long=value` arguments for the
+/// option with the same long name, skipping options that `explicit` already
+/// got from the command line or environment. Keys may use `_` or `-`.
+fn file_args(command: &Command, explicit: &ArgMatches, settings: toml::Table) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (key, value) in settings {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != CONFIG_ARG)
+            .ok_or_else(|| format!("unknown setting `{}`", key))?;
+        let values = match value {
+            toml::Value::Array(items) if matches!(arg.get_action(), ArgAction::Append) => {
+                items.into_iter().map(|item| scalar(&key, item)).collect::<Result<Vec<_>, _>>()?
+            }
+            toml::Value::Array(_) => return Err(format!("`{}` takes a single value, not a list", key)),
+            value => vec![scalar(&key, value)?],
+        };
+        if matches!(
+            explicit.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        for value in values {
+            match arg.get_action() {
+                ArgAction::SetTrue => match value.as_str() {
+                    "true" => args.push(format!("--{}", long).into()),
+                    "false" => {}
+                    _ => return Err(format!("`{}` must be true or false", key)),
+                },
+                _ => args.push(format!("--{}={}", long, value).into()),
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("`{}` must be a string, number or boolean", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, StoreBackend};
+
+    /// Parses `Args` from `cli` with a config file holding `contents`.
+    fn parse_with_file(name: &str, contents: &str, cli: &[&str]) -> Result<Args, clap::Error> {
+        let path = std::env::temp_dir().join(format!("rsdrop-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let config = path.to_str().unwrap();
+        let args = ["rsDrop", "--config", config].into_iter().chain(cli.iter().copied());
+        let result = parse_from(Args::command(), args);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn file_settings_fill_unset_options() {
+        let args = parse_with_file("fill", "max_paste_bytes = 4096\nno-request-logs = true\n", &[]).unwrap();
+        assert_eq!(args.max_paste_bytes, 4096);
+        assert!(args.no_request_logs);
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let args = parse_with_file("override", "max_paste_bytes = 4096\n", &["--max-paste-bytes", "512"]).unwrap();
+        assert_eq!(args.max_paste_bytes, 512);
+    }
+
+    #[test]
+    fn file_list_for_repeatable_option() {
+        let args = parse_with_file("list", "trusted_proxy = [\"10.0.0.0/8\", \"192.168.0.1\"]\n", &[]).unwrap();
+        assert_eq!(args.trusted_proxies.len(), 2);
+    }
+
+    #[test]
+    fn file_list_for_single_value_option_is_rejected() {
+        let e = parse_with_file("single", "max_paste_bytes = [1, 2]\n", &[]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidValue);
+        assert!(e.to_string().contains("takes a single value"), "{}", e);
+    }
+
+    #[test]
+    fn unknown_file_setting_is_rejected() {
+        let e = parse_with_file("unknown", "max_pastebytes = 1\n", &[]).unwrap_err();
+        assert!(e.to_string().contains("unknown setting `max_pastebytes`"), "{}", e);
+    }
+
+    #[test]
+    fn invalid_file_value_is_rejected() {
+        let e = parse_with_file("invalid", "max_paste_bytes = \"lots\"\n", &[]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn persistent_store_from_file_requires_data_dir() {
+        let e = parse_with_file("no-data-dir", "store = \"sqlite\"\n", &[]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+
+        let args = parse_with_file("data-dir", "store = \"sqlite\"\n", &["--data-dir", "/tmp/rsdrop"]).unwrap();
+        assert!(matches!(args.store, StoreBackend::Sqlite));
+    }
+
+    #[test]
+    fn file_settings_obey_requires() {
+        let e = parse_with_file("requires", "proxy_protocol = true\n", &[]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::MissingRequiredArgument);
+    }
+}
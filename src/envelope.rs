@@ -0,0 +1,275 @@
+use crate::store::EncryptedBlob;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// --- Envelope Format ---
+// Version 1 of the ciphertext envelope, as sent by clients:
+//
+//   offset  size  field
+//   0       1     version (1)
+//   1       1     algorithm ID (see `Algorithm`)
+//   2       1     KDF ID (see `Kdf`)
+//   3       n     nonce; n is fixed by the algorithm
+//   3 + n   rest  ciphertext, including the authentication tag
+//
+// Stored pastes keep the algorithm and KDF IDs next to the nonce and
+// ciphertext. Pastes stored before envelopes existed have no IDs and are
+// AES-256-GCM, which is also what a bare `nonce_b64` upload still means.
+pub const ENVELOPE_VERSION: u8 = 1;
+const HEADER_LENGTH: usize = 3; // Version, algorithm ID, KDF ID
+const TAG_LENGTH: usize = 16; // GCM and Poly1305 tags alike
+
+// --- Data Structures ---
+/// Authenticated encryption scheme of a paste's ciphertext. The server never
+/// decrypts; it only needs the nonce length to validate uploads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    /// ID 1, 12-byte nonce. The only scheme before envelopes existed.
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// ID 2, 24-byte nonce.
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Aes256Gcm),
+            2 => Some(Self::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Aes256Gcm => 1,
+            Self::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub fn nonce_length(self) -> usize {
+        match self {
+            Self::Aes256Gcm => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    pub fn validate_nonce(self, nonce: &[u8]) -> Result<(), EnvelopeError> {
+        if nonce.len() != self.nonce_length() {
+            return Err(EnvelopeError::NonceLength {
+                algorithm: self,
+                actual: nonce.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Aes256Gcm => write!(f, "AES-256-GCM"),
+            Self::XChaCha20Poly1305 => write!(f, "XChaCha20-Poly1305"),
+        }
+    }
+}
+
+/// How the viewer turns the link fragment into a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Kdf {
+    /// ID 0: the key comes from the link fragment alone.
+    #[serde(rename = "none")]
+    None,
+    /// ID 1: a PBKDF2-SHA256 passphrase layer, with parameters in `password_kdf`.
+    #[serde(rename = "pbkdf2-sha256")]
+    Pbkdf2Sha256,
+}
+
+impl Kdf {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::None),
+            1 => Some(Self::Pbkdf2Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Pbkdf2Sha256 => 1,
+        }
+    }
+}
+
+/// The scheme recorded for a paste; applies to every blob it holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cipher {
+    pub algorithm: Algorithm,
+    pub kdf: Kdf,
+}
+
+impl Cipher {
+    /// Scheme of uploads that sent a bare nonce instead of an envelope.
+    pub fn legacy(has_passphrase: bool) -> Self {
+        Self {
+            algorithm: Algorithm::Aes256Gcm,
+            kdf: if has_passphrase { Kdf::Pbkdf2Sha256 } else { Kdf::None },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownAlgorithm(u8),
+    UnknownKdf(u8),
+    NonceLength { algorithm: Algorithm, actual: usize },
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "Envelope is truncated"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported envelope version {}. Expected {}", version, ENVELOPE_VERSION)
+            }
+            Self::UnknownAlgorithm(id) => write!(f, "Unknown envelope algorithm ID {}", id),
+            Self::UnknownKdf(id) => write!(f, "Unknown envelope KDF ID {}", id),
+            Self::NonceLength { algorithm, actual } => write!(
+                f,
+                "Invalid nonce length {} for {}. Expected {}",
+                actual,
+                algorithm,
+                algorithm.nonce_length()
+            ),
+        }
+    }
+}
+
+// --- Encoding ---
+/// Splits a complete envelope into its scheme and ciphertext.
+pub fn open(envelope: &[u8]) -> Result<(Cipher, EncryptedBlob), EnvelopeError> {
+    let (cipher, nonce_length) = parse_header(envelope)?;
+    let Some((nonce, encrypted_data)) = envelope[HEADER_LENGTH..].split_at_checked(nonce_length) else {
+        return Err(EnvelopeError::Truncated);
+    };
+    if encrypted_data.len() < TAG_LENGTH {
+        return Err(EnvelopeError::Truncated);
+    }
+    let blob = EncryptedBlob {
+        encrypted_data: encrypted_data.to_vec(),
+        nonce: nonce.to_vec(),
+    };
+    Ok((cipher, blob))
+}
+
+/// Parses an envelope without its ciphertext, as sent in the `X-Envelope`
+/// header of raw uploads whose body carries the ciphertext.
+pub fn open_prefix(prefix: &[u8]) -> Result<(Cipher, Vec<u8>), EnvelopeError> {
+    let (cipher, nonce_length) = parse_header(prefix)?;
+    let nonce = &prefix[HEADER_LENGTH..];
+    if nonce.len() != nonce_length {
+        return Err(EnvelopeError::NonceLength {
+            algorithm: cipher.algorithm,
+            actual: nonce.len(),
+        });
+    }
+    Ok((cipher, nonce.to_vec()))
+}
+
+/// Inverse of [`open_prefix`], used for raw downloads.
+pub fn seal_prefix(cipher: Cipher, nonce: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(HEADER_LENGTH + nonce.len());
+    prefix.extend_from_slice(&[ENVELOPE_VERSION, cipher.algorithm.id(), cipher.kdf.id()]);
+    prefix.extend_from_slice(nonce);
+    prefix
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Cipher, usize), EnvelopeError> {
+    let [version, algorithm, kdf, ..] = *bytes else {
+        return Err(EnvelopeError::Truncated);
+    };
+    if version != ENVELOPE_VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(version));
+    }
+    let algorithm = Algorithm::from_id(algorithm).ok_or(EnvelopeError::UnknownAlgorithm(algorithm))?;
+    let kdf = Kdf::from_id(kdf).ok_or(EnvelopeError::UnknownKdf(kdf))?;
+    Ok((Cipher { algorithm, kdf }, algorithm.nonce_length()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AES: Cipher = Cipher {
+        algorithm: Algorithm::Aes256Gcm,
+        kdf: Kdf::None,
+    };
+
+    fn envelope(header: [u8; HEADER_LENGTH], nonce_length: usize, ciphertext_length: usize) -> Vec<u8> {
+        let mut envelope = header.to_vec();
+        envelope.extend(std::iter::repeat_n(0xaa, nonce_length));
+        envelope.extend(std::iter::repeat_n(0xbb, ciphertext_length));
+        envelope
+    }
+
+    #[test]
+    fn opens_each_algorithm() {
+        let (cipher, blob) = open(&envelope([1, 1, 0], 12, TAG_LENGTH + 5)).unwrap();
+        assert_eq!(cipher, AES);
+        assert_eq!(blob.nonce, vec![0xaa; 12]);
+        assert_eq!(blob.encrypted_data, vec![0xbb; TAG_LENGTH + 5]);
+
+        let (cipher, blob) = open(&envelope([1, 2, 1], 24, TAG_LENGTH)).unwrap();
+        assert_eq!(cipher.algorithm, Algorithm::XChaCha20Poly1305);
+        assert_eq!(cipher.kdf, Kdf::Pbkdf2Sha256);
+        assert_eq!(blob.nonce.len(), 24);
+    }
+
+    #[test]
+    fn rejects_unknown_header_fields() {
+        assert_eq!(open(&envelope([0, 1, 0], 12, 20)).err(), Some(EnvelopeError::UnsupportedVersion(0)));
+        assert_eq!(open(&envelope([2, 1, 0], 12, 20)).err(), Some(EnvelopeError::UnsupportedVersion(2)));
+        assert_eq!(open(&envelope([1, 3, 0], 12, 20)).err(), Some(EnvelopeError::UnknownAlgorithm(3)));
+        assert_eq!(open(&envelope([1, 1, 9], 12, 20)).err(), Some(EnvelopeError::UnknownKdf(9)));
+    }
+
+    #[test]
+    fn rejects_truncated_envelopes() {
+        assert_eq!(open(&[]).err(), Some(EnvelopeError::Truncated));
+        assert_eq!(open(&[1, 1]).err(), Some(EnvelopeError::Truncated));
+        // An XChaCha20 envelope carrying only an AES-sized nonce and tag
+        assert_eq!(open(&envelope([1, 2, 0], 12, TAG_LENGTH)).err(), Some(EnvelopeError::Truncated));
+        assert_eq!(open(&envelope([1, 1, 0], 12, TAG_LENGTH - 1)).err(), Some(EnvelopeError::Truncated));
+    }
+
+    #[test]
+    fn prefix_requires_the_exact_nonce_length() {
+        let prefix = seal_prefix(AES, &[0xaa; 12]);
+        assert_eq!(open_prefix(&prefix), Ok((AES, vec![0xaa; 12])));
+
+        let nonce_length = |actual| EnvelopeError::NonceLength {
+            algorithm: Algorithm::Aes256Gcm,
+            actual,
+        };
+        assert_eq!(open_prefix(&envelope([1, 1, 0], 11, 0)), Err(nonce_length(11)));
+        assert_eq!(open_prefix(&envelope([1, 1, 0], 24, 0)), Err(nonce_length(24)));
+        assert_eq!(open_prefix(&[1, 1, 0]), Err(nonce_length(0)));
+    }
+
+    #[test]
+    fn validates_bare_nonces() {
+        assert_eq!(Algorithm::XChaCha20Poly1305.validate_nonce(&[0; 24]), Ok(()));
+        assert_eq!(
+            Algorithm::XChaCha20Poly1305.validate_nonce(&[0; 12]),
+            Err(EnvelopeError::NonceLength {
+                algorithm: Algorithm::XChaCha20Poly1305,
+                actual: 12,
+            })
+        );
+    }
+}
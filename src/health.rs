@@ -0,0 +1,127 @@
+use crate::{
+    store::{unix_now, CapacityPolicy},
+    SharedState,
+};
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::warn;
+
+// --- Configuration Constants ---
+const MISSED_CLEANUPS_BEFORE_UNHEALTHY: u32 = 3;
+const STORE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// --- Data Structures ---
+/// What the health endpoints report on besides the store itself.
+pub struct Health {
+    /// Unix seconds of the cleanup task's latest tick.
+    last_cleanup: AtomicU64,
+    tls_enabled: bool,
+    /// Resident memory above which the server reports itself not ready.
+    max_memory_bytes: Option<u64>,
+}
+
+impl Health {
+    pub fn new(tls_enabled: bool, max_memory_bytes: Option<u64>) -> Self {
+        Self {
+            last_cleanup: AtomicU64::new(unix_now()),
+            tls_enabled,
+            max_memory_bytes,
+        }
+    }
+
+    pub fn cleanup_ticked(&self) {
+        self.last_cleanup.store(unix_now(), Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize)]
+pub struct LivenessResponse {
+    status: &'static str,
+    last_cleanup_secs_ago: u64,
+}
+
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    status: &'static str,
+    store: String,
+    tls: &'static str,
+    memory: String,
+}
+
+// --- Route Handlers ---
+/// Fails when the cleanup task has missed several ticks, which means the
+/// runtime or the store is wedged and a restart is due.
+pub async fn handle_healthz(State(state): State<SharedState>) -> (StatusCode, Json<LivenessResponse>) {
+    let last_cleanup_secs_ago = unix_now().saturating_sub(state.health.last_cleanup.load(Ordering::Relaxed));
+    let deadline = state.config.cleanup_interval.as_secs() * u64::from(MISSED_CLEANUPS_BEFORE_UNHEALTHY);
+    let (status_code, status) = if last_cleanup_secs_ago > deadline {
+        warn!("Liveness check failed: cleanup task last ran {}s ago", last_cleanup_secs_ago);
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+    let response = LivenessResponse {
+        status,
+        last_cleanup_secs_ago,
+    };
+    (status_code, Json(response))
+}
+
+/// Ready when the store answers and, under the `reject` capacity policy, has
+/// room for more pastes, TLS material (if configured) is loaded and resident
+/// memory is under `--max-memory-bytes`. Evicting policies always make room,
+/// so a full store only counts against readiness when it refuses creates.
+pub async fn handle_readyz(State(state): State<SharedState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let mut ready = true;
+    let store = match tokio::time::timeout(STORE_CHECK_TIMEOUT, state.store.stats()).await {
+        Ok(Ok(stats)) => {
+            let capacity = state.config.capacity;
+            let full = stats.paste_count >= capacity.max_pastes || stats.total_bytes >= capacity.max_total_bytes;
+            if full && capacity.policy == CapacityPolicy::Reject {
+                ready = false;
+                format!("full ({} pastes, {} bytes)", stats.paste_count, stats.total_bytes)
+            } else {
+                "ok".to_string()
+            }
+        }
+        Ok(Err(e)) => {
+            ready = false;
+            format!("error: {}", e)
+        }
+        Err(_) => {
+            ready = false;
+            format!("no answer within {}s", STORE_CHECK_TIMEOUT.as_secs())
+        }
+    };
+    // TLS material is loaded before the server starts listening, so a
+    // running TLS server always has it.
+    let tls = if state.health.tls_enabled { "loaded" } else { "disabled" };
+    let memory = match (state.health.max_memory_bytes, resident_memory_bytes()) {
+        (Some(max), Some(resident)) if resident > max => {
+            ready = false;
+            format!("{} bytes resident, over the {} byte cap", resident, max)
+        }
+        (Some(_), None) => "unknown".to_string(),
+        (_, Some(resident)) => format!("{} bytes resident", resident),
+        (None, None) => "unchecked".to_string(),
+    };
+    let (status_code, status) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        warn!("Readiness check failed: store {}, memory {}", store, memory);
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    };
+    (status_code, Json(ReadinessResponse { status, store, tls, memory }))
+}
+
+/// Resident set size of this process, from `/proc` where available.
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.trim_start_matches("VmRSS:").trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
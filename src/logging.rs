@@ -0,0 +1,113 @@
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use rand::Rng;
+use sha2::Sha256;
+use std::{fmt, sync::OnceLock};
+use tracing_subscriber::{
+    filter::{self, Targets},
+    layer::{Layer, SubscriberExt},
+    util::SubscriberInitExt,
+};
+
+// --- Configuration Constants ---
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const REQUEST_SPAN: &str = "request"; // Opened around every routed request by `telemetry::request_span`
+const HASHED_ID_LENGTH: usize = 6; // Bytes of the keyed hash shown in place of a paste ID
+
+// --- Data Structures ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One readable line per event.
+    Human,
+    /// One JSON object per event, with the request's route and method.
+    Json,
+}
+
+/// How paste IDs appear in log lines. Anyone holding an ID can fetch the
+/// ciphertext, so logs should not carry them in the clear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PasteIdLogging {
+    /// A short keyed hash that correlates lines about the same paste until
+    /// the server restarts, but cannot be turned back into the ID.
+    Hash,
+    /// A fixed placeholder.
+    Redact,
+    /// The ID itself; for debugging only.
+    Full,
+}
+
+/// How paste IDs are written; set once at startup.
+enum IdWriter {
+    Hash(Box<[u8; 32]>),
+    Redact,
+    Full,
+}
+
+static ID_WRITER: OnceLock<IdWriter> = OnceLock::new();
+
+/// A paste ID as it may appear in logs, according to `--log-paste-ids`.
+pub struct LoggedId<'a>(&'a str);
+
+pub fn paste_id(id: &str) -> LoggedId<'_> {
+    LoggedId(id)
+}
+
+impl fmt::Display for LoggedId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match ID_WRITER.get().unwrap_or(&IdWriter::Redact) {
+            IdWriter::Hash(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_slice()).expect("HMAC accepts any key length");
+                mac.update(self.0.as_bytes());
+                write!(f, "id#")?;
+                for byte in &mac.finalize().into_bytes()[..HASHED_ID_LENGTH] {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            IdWriter::Redact => write!(f, "[redacted]"),
+            IdWriter::Full => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Installs the global subscriber. With `request_logs` off, every event
+/// emitted while handling a request is dropped, so only startup, background
+/// task and shutdown events are written. Spans, but no events, go to the
+/// tracer provider when one is given.
+pub fn init(
+    format: LogFormat,
+    level: Targets,
+    paste_ids: PasteIdLogging,
+    request_logs: bool,
+    tracer_provider: Option<&SdkTracerProvider>,
+) {
+    let writer = match paste_ids {
+        PasteIdLogging::Hash => IdWriter::Hash(Box::new(rand::thread_rng().r#gen())),
+        PasteIdLogging::Redact => IdWriter::Redact,
+        PasteIdLogging::Full => IdWriter::Full,
+    };
+    let _ = ID_WRITER.set(writer);
+
+    let output = match format {
+        LogFormat::Human => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().with_current_span(true).boxed(),
+    };
+    let outside_requests = filter::dynamic_filter_fn(move |metadata, cx| {
+        request_logs
+            || metadata.is_span()
+            || !cx
+                .lookup_current()
+                .is_some_and(|span| span.scope().any(|span| span.name() == REQUEST_SPAN))
+    });
+    let traces = tracer_provider.map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+            .with_filter(filter::filter_fn(|metadata| metadata.is_span()))
+    });
+    tracing_subscriber::registry()
+        .with(traces)
+        .with(output.with_filter(outside_requests).with_filter(level))
+        .init();
+}
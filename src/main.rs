@@ -1,76 +1,466 @@
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::Html,
-    routing::{get, post},
+    body::Body,
+    extract::{DefaultBodyLimit, Extension, Json, Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put, MethodRouter},
     Router,
 };
+use auth::{ApiKey, ApiKeys};
 use axum_server::{tls_rustls::RustlsConfig, Handle};
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine as _};
 use clap::Parser;
+use envelope::{Algorithm, Cipher, Kdf};
+use futures_util::StreamExt;
+use health::Health;
+use logging::{LogFormat, PasteIdLogging};
+use metrics::Metrics;
+use pow::{PowConfig, PowError, ProofOfWork, Redemption};
+use proxy::{Cidr, ProxyProtocolAcceptor, TrustedProxies};
 use rand::{distributions::Alphanumeric, Rng};
+use ratelimit::{BucketLimit, RateLimiter, RateLimits};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
     net::SocketAddr,
     path::PathBuf,
     sync::Arc,
-    time::{Duration, Instant},
+    time::Duration,
 };
-use tokio::sync::RwLock;
-use tracing::{error, info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use store::{
+    unix_now, AccessFailure, AccessFailurePolicy, AccessGuard, AccessLimits, BundleItem, CapacityLimits,
+    CapacityPolicy, CappedStore, EncryptedBlob, EncryptedPaste, PasteStore, StoreBackend, StoreError,
+};
+use subtle::ConstantTimeEq;
+use tracing::{error, field, info, instrument, warn, Span};
+use tracing_subscriber::filter::Targets;
+
+mod auth;
+mod config;
+mod envelope;
+mod health;
+mod logging;
+mod metrics;
+mod pow;
+mod proxy;
+mod ratelimit;
+mod store;
+mod telemetry;
+mod upload;
 
 // --- Configuration Constants ---
-const MAX_ENCRYPTED_SIZE: usize = 10 * 1024 * 1024; // 10 MiB limit (encrypted data + nonce)
-const PASTE_ID_LENGTH: usize = 22; // Length of the random URL-safe ID
-const NONCE_LENGTH: usize = 12; // Standard AES-GCM nonce length
-const EXPIRY_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
-const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
-const WEB_DIR: &str = "./web";
+const DEFAULT_MAX_PASTE_BYTES: usize = 10 * 1024 * 1024; // 10 MiB limit (encrypted data + nonce)
+const JSON_BODY_OVERHEAD: usize = 64 * 1024; // Field names, metadata and bundle framing around the base64 ciphertext
+const SMALL_JSON_BODY_LIMIT: usize = 64 * 1024; // Upload session requests, which carry no ciphertext
+const DEFAULT_PASTE_ID_LENGTH: usize = 22; // Length of the random URL-safe ID
+const MIN_PASTE_ID_LENGTH: usize = 16; // Anyone who guesses an ID can fetch the ciphertext
+const MAX_PASTE_ID_LENGTH: usize = 50; // Longer IDs are rejected before any lookup
+const DELETION_TOKEN_LENGTH: usize = 32; // Length of the creator's secret deletion token
+const DELETION_TOKEN_HEADER: &str = "x-deletion-token";
+const NONCE_HEADER: &str = "x-nonce"; // Base64 nonce for raw uploads and downloads
+const ENVELOPE_HEADER: &str = "x-envelope"; // Base64 envelope minus its ciphertext; replaces X-Nonce
+const EXPIRES_IN_HEADER: &str = "x-expires-in";
+const MAX_VIEWS_HEADER: &str = "x-max-views";
+const METADATA_HEADER: &str = "x-metadata"; // Base64 encrypted file metadata for raw uploads and downloads
+const METADATA_NONCE_HEADER: &str = "x-metadata-nonce";
+const PASSWORD_KDF_HEADER: &str = "x-password-kdf"; // Base64 passphrase KDF parameters for raw uploads and downloads
+const MAX_METADATA_SIZE: usize = 4 * 1024; // Encrypted file name, MIME type and size
+const MAX_PASSWORD_KDF_SIZE: usize = 512; // Passphrase KDF parameters and salt
+const ACCESS_TOKEN_HEADER: &str = "x-access-token"; // Base64 proof of the passphrase for guarded pastes
+const ACCESS_TOKEN_LENGTH: usize = 32; // HMAC-SHA256 output
+const DEFAULT_MAX_ACCESS_ATTEMPTS: u32 = 5; // Wrong access tokens before lockout or burn
+const ACCESS_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+const MAX_BUNDLE_ITEMS: usize = 32; // Items per bundle; their total size still counts against --max-paste-bytes
+const DEFAULT_EXPIRY_SECS: u64 = 24 * 60 * 60; // Used when the creator does not pick one
+const EXPIRY_CHOICES: &[u64] = &[5 * 60, 60 * 60, 24 * 60 * 60, 7 * 24 * 60 * 60]; // 5 min, 1 hour, 1 day, 1 week
+const DEFAULT_MAX_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_VIEWS_LIMIT: u32 = 100; // Highest view count a creator may set
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 256 * 1024 * 1024; // 256 MiB per chunked upload
+const DEFAULT_CLEANUP_INTERVAL_SECS: u64 = 60 * 60;
+const DEFAULT_WEB_DIR: &str = "./web";
+const WEB_PAGES: &[&str] = &["index.html", "retrieve.html", "delete.html"];
+const DEFAULT_MAX_TOTAL_BYTES: usize = 512 * 1024 * 1024; // 512 MiB across all pastes
+const DEFAULT_MAX_PASTES: usize = 100_000;
 
 // --- Command Line Arguments ---
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// TOML file with settings for any of these options, keyed by long name (e.g. `max_paste_bytes = 1048576`).
+    /// Command-line options and RSDROP_* environment variables take precedence over it.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
     #[arg(long)]
     cert: Option<PathBuf>,
     #[arg(long)]
     key: Option<PathBuf>,
     #[arg(long, default_value = "0.0.0.0:8080")]
     addr: SocketAddr,
+    /// Log output format.
+    #[arg(long, value_enum, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+    /// Log level, optionally per module, e.g. "warn" or "info,rsDrop::store=debug".
+    #[arg(long, default_value = logging::DEFAULT_LOG_LEVEL)]
+    log_level: Targets,
+    /// How paste IDs appear in logs. Anyone who reads an ID from the logs can fetch the ciphertext.
+    #[arg(long, value_enum, default_value_t = PasteIdLogging::Hash)]
+    log_paste_ids: PasteIdLogging,
+    /// Write nothing while handling requests; only startup, background tasks and shutdown are logged.
+    #[arg(long)]
+    no_request_logs: bool,
+    /// Export traces to this OTLP/HTTP collector URL, e.g. http://localhost:4318/v1/traces.
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+    /// Service name reported with exported traces.
+    #[arg(long, default_value = telemetry::DEFAULT_SERVICE_NAME, requires = "otlp_endpoint")]
+    otlp_service_name: String,
+    /// Fraction of requests to trace, from 0 to 1. Requests whose caller sampled them are always traced.
+    #[arg(long, default_value_t = 1.0, value_parser = telemetry::parse_sample_ratio, requires = "otlp_endpoint")]
+    otlp_sample_ratio: f64,
+    /// Serve /metrics over plain HTTP on this separate admin address instead of the main one.
+    /// The health endpoints are served there too.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Resident memory, in bytes, above which /readyz reports the server as not ready.
+    #[arg(long)]
+    max_memory_bytes: Option<u64>,
+    /// Address range (CIDR) of a reverse proxy whose X-Forwarded-For, Forwarded and PROXY protocol
+    /// headers are trusted. May be repeated or comma-separated.
+    #[arg(long = "trusted-proxy", value_name = "CIDR", value_delimiter = ',')]
+    trusted_proxies: Vec<Cidr>,
+    /// Expect a PROXY protocol v1 or v2 header on every connection; connections from
+    /// addresses outside --trusted-proxy are dropped.
+    #[arg(long, requires = "trusted_proxies")]
+    proxy_protocol: bool,
+    /// Storage backend used to hold encrypted pastes.
+    #[arg(long, value_enum, default_value_t = StoreBackend::Memory)]
+    store: StoreBackend,
+    /// Directory used by persistent storage backends.
+    #[arg(long, required_if_eq_any([("store", "file"), ("store", "sqlite")]))]
+    data_dir: Option<PathBuf>,
+    /// Maximum bytes stored across all pastes.
+    #[arg(long, default_value_t = DEFAULT_MAX_TOTAL_BYTES)]
+    max_total_bytes: usize,
+    /// Maximum number of stored pastes.
+    #[arg(long, default_value_t = DEFAULT_MAX_PASTES)]
+    max_pastes: usize,
+    /// What to do when a new paste would exceed the storage limits.
+    #[arg(long, value_enum, default_value_t = CapacityPolicy::Reject)]
+    capacity_policy: CapacityPolicy,
+    /// Longest lifetime, in seconds, a creator may request for a paste.
+    #[arg(long, default_value_t = DEFAULT_MAX_EXPIRY_SECS)]
+    max_expiry_secs: u64,
+    /// Lifetime, in seconds, of pastes whose creator does not pick one.
+    #[arg(long, default_value_t = DEFAULT_EXPIRY_SECS)]
+    default_expiry_secs: u64,
+    /// Maximum size of one paste or chunk, in bytes of ciphertext and nonce.
+    #[arg(long, default_value_t = DEFAULT_MAX_PASTE_BYTES)]
+    max_paste_bytes: usize,
+    /// Length of new paste IDs. Existing pastes keep their IDs.
+    #[arg(long, default_value_t = DEFAULT_PASTE_ID_LENGTH)]
+    paste_id_length: usize,
+    /// Seconds between runs of the task that removes expired pastes.
+    #[arg(long, default_value_t = DEFAULT_CLEANUP_INTERVAL_SECS, value_parser = clap::value_parser!(u64).range(1..))]
+    cleanup_interval_secs: u64,
+    /// Directory holding the HTML pages.
+    #[arg(long, default_value = DEFAULT_WEB_DIR)]
+    web_dir: PathBuf,
+    /// Maximum total size of a chunked upload, in bytes.
+    #[arg(long, default_value_t = DEFAULT_MAX_UPLOAD_BYTES)]
+    max_upload_bytes: usize,
+    /// Wrong passphrase proofs a guarded paste tolerates before the failure policy applies.
+    #[arg(long, default_value_t = DEFAULT_MAX_ACCESS_ATTEMPTS, value_parser = clap::value_parser!(u32).range(1..))]
+    max_access_attempts: u32,
+    /// What happens to a guarded paste after too many wrong passphrase proofs.
+    #[arg(long, value_enum, default_value_t = AccessFailurePolicy::Lockout)]
+    access_failure_policy: AccessFailurePolicy,
+    /// New pastes and upload sessions allowed per client IP per minute (0 disables the limit).
+    #[arg(long, default_value_t = ratelimit::DEFAULT_CREATE_PER_MINUTE)]
+    create_rate_limit: u32,
+    /// Creates a client IP may make in a burst before the per-minute rate applies.
+    #[arg(long, default_value_t = ratelimit::DEFAULT_CREATE_BURST)]
+    create_burst: u32,
+    /// Paste reads allowed per client IP per minute (0 disables the limit).
+    #[arg(long, default_value_t = ratelimit::DEFAULT_READ_PER_MINUTE)]
+    read_rate_limit: u32,
+    /// Reads a client IP may make in a burst before the per-minute rate applies.
+    #[arg(long, default_value_t = ratelimit::DEFAULT_READ_BURST)]
+    read_burst: u32,
+    /// Lookups of missing pastes allowed per client IP per minute before all its reads are refused (0 disables the limit).
+    #[arg(long, default_value_t = ratelimit::DEFAULT_FAILED_LOOKUP_PER_MINUTE)]
+    failed_lookup_rate_limit: u32,
+    /// Missing-paste lookups a client IP may make in a burst.
+    #[arg(long, default_value_t = ratelimit::DEFAULT_FAILED_LOOKUP_BURST)]
+    failed_lookup_burst: u32,
+    /// Require a bearer API key for creating pastes, checked against the hashed keys, labels and
+    /// quotas in this JSON file. Reads stay anonymous.
+    #[arg(long, value_name = "FILE")]
+    api_keys: Option<PathBuf>,
+    /// Require a hashcash-style proof of work for new pastes, starting at this many leading zero bits.
+    #[arg(long, value_name = "BITS", value_parser = clap::value_parser!(u8).range(1..=32))]
+    pow_difficulty: Option<u8>,
+    /// Highest difficulty that paste size and load may raise challenges to.
+    #[arg(long, value_name = "BITS", default_value_t = pow::DEFAULT_MAX_DIFFICULTY,
+          value_parser = clap::value_parser!(u8).range(1..=32), requires = "pow_difficulty")]
+    pow_max_difficulty: u8,
+    /// Creates per minute above which challenges get harder, one bit per doubling (0 disables).
+    #[arg(long, default_value_t = pow::DEFAULT_LOAD_THRESHOLD, requires = "pow_difficulty")]
+    pow_load_threshold: u32,
+    /// File whose contents key the challenge signatures, so several instances accept each other's
+    /// challenges. A random key is used when omitted.
+    #[arg(long, requires = "pow_difficulty")]
+    pow_key_file: Option<PathBuf>,
 }
 
 // --- Data Structures ---
-#[derive(Clone)]
-struct EncryptedPaste {
-    encrypted_data: Vec<u8>,
-    nonce: Vec<u8>,
-    timestamp: Instant,
-}
-
 #[derive(Deserialize)]
 struct CreateEncryptedPasteRequest {
+    /// Versioned envelope holding the scheme, nonce and ciphertext. Replaces
+    /// `encrypted_data_b64` and `nonce_b64`, which still mean AES-256-GCM.
+    #[serde(default)]
+    envelope_b64: Option<String>,
+    #[serde(default)]
+    encrypted_data_b64: String,
+    #[serde(default)]
+    nonce_b64: String,
+    /// Requested lifetime; must be one of `EXPIRY_CHOICES`.
+    #[serde(default)]
+    expires_in_secs: Option<u64>,
+    /// Delete the paste after this many reads (1 = burn after reading).
+    #[serde(default)]
+    max_views: Option<u32>,
+    /// Encrypted file metadata for attachments, with its own nonce.
+    #[serde(default)]
+    encrypted_metadata_b64: Option<String>,
+    #[serde(default)]
+    metadata_nonce_b64: Option<String>,
+    /// Additional items that turn this paste into a bundle.
+    #[serde(default)]
+    items: Vec<BundleItemPayload>,
+    /// Passphrase KDF parameters and salt; stored as-is for the viewer.
+    #[serde(default)]
+    password_kdf_b64: Option<String>,
+    /// Access token derived from the key and passphrase. When set, only its
+    /// hash is stored and readers must present the token to get the ciphertext.
+    #[serde(default)]
+    access_token_b64: Option<String>,
+    /// Solved challenge from `/api/challenge`, when the server asks for proof of work.
+    #[serde(default)]
+    pow_challenge: Option<String>,
+    #[serde(default)]
+    pow_nonce: Option<String>,
+}
+
+/// One encrypted bundle item, used both in create requests and in responses.
+#[derive(Serialize, Deserialize)]
+struct BundleItemPayload {
     encrypted_data_b64: String,
     nonce_b64: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_metadata_b64: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata_nonce_b64: Option<String>,
+}
+
+/// Everything a new paste is made of, as received from one of the upload endpoints.
+#[derive(Default)]
+struct NewPaste {
+    /// Scheme from the upload's envelope; `None` for uploads with a bare nonce.
+    cipher: Option<Cipher>,
+    content: EncryptedBlob,
+    chunks: Arc<Vec<EncryptedBlob>>,
+    metadata: Option<EncryptedBlob>,
+    items: Vec<BundleItem>,
+    password_kdf: Option<Vec<u8>>,
+    access_guard: Option<AccessGuard>,
+}
+
+/// Creator-chosen options shared by every upload endpoint.
+struct PasteOptions {
+    expires_in_secs: Option<u64>,
+    max_views: Option<u32>,
+}
+
+/// Proof of work sent with a new paste, and the size in bytes it has to cover.
+struct PowProof<'a> {
+    challenge: Option<&'a str>,
+    nonce: Option<&'a str>,
+    size: usize,
 }
 
 #[derive(Serialize)]
 struct CreateEncryptedPasteResponse {
     paste_id: String,
+    expires_in_secs: u64,
+    /// Secret that lets the creator delete the paste early; only its hash is kept.
+    deletion_token: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum GetPasteResponse {
+    Paste(GetEncryptedPasteResponse),
+    Sealed(SealedPasteResponse),
+}
+
+/// Returned instead of the ciphertext for view-limited pastes, which must be
+/// fetched with an explicit `POST /api/paste/:paste_id/reveal`.
+#[derive(Serialize)]
+struct SealedPasteResponse {
+    reveal_required: bool,
+    /// The paste is guarded: repeat the request with an `X-Access-Token` header.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    access_required: bool,
+    expires_in_secs: u64,
+    remaining_views: Option<u32>,
+    /// Lets the viewer check it can decrypt the paste before spending a view.
+    cipher: Cipher,
+    /// Lets the viewer ask for the passphrase before spending a view.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_kdf_b64: Option<String>,
 }
 
 #[derive(Serialize)]
 struct GetEncryptedPasteResponse {
     encrypted_data_b64: String,
     nonce_b64: String,
+    /// Algorithm and KDF of every blob in the paste.
+    cipher: Cipher,
+    expires_in_secs: u64,
+    /// Views left after this one; absent for pastes without a view limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_views: Option<u32>,
+    /// Number of chunks to fetch from `/api/paste/:paste_id/chunks/:index`;
+    /// present only for pastes created through the chunked upload API, whose
+    /// `encrypted_data_b64` and `nonce_b64` are empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk_count: Option<usize>,
+    /// Encrypted file metadata; absent for plain text pastes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encrypted_metadata_b64: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata_nonce_b64: Option<String>,
+    /// Items of a bundle, in order; absent for single pastes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    items: Vec<BundleItemPayload>,
+    /// Passphrase KDF parameters; present only for password-protected pastes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_kdf_b64: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StoreStatsResponse {
+    paste_count: usize,
+    total_bytes: usize,
+    max_pastes: usize,
+    max_total_bytes: usize,
 }
 
 #[derive(Clone)]
-struct AppConfig {}
+struct AppConfig {
+    store_backend: StoreBackend,
+    data_dir: Option<PathBuf>,
+    capacity: CapacityLimits,
+    max_expiry: Duration,
+    default_expiry: Duration,
+    max_paste_bytes: usize,
+    paste_id_length: usize,
+    cleanup_interval: Duration,
+    web_dir: PathBuf,
+    max_upload_bytes: usize,
+    access: AccessLimits,
+    rate_limits: RateLimits,
+    trusted_proxies: TrustedProxies,
+}
+
+impl AppConfig {
+    fn from_args(args: &Args) -> Self {
+        Self {
+            store_backend: args.store,
+            data_dir: args.data_dir.clone(),
+            capacity: CapacityLimits {
+                max_total_bytes: args.max_total_bytes,
+                max_pastes: args.max_pastes,
+                policy: args.capacity_policy,
+            },
+            max_expiry: Duration::from_secs(args.max_expiry_secs),
+            default_expiry: Duration::from_secs(args.default_expiry_secs),
+            max_paste_bytes: args.max_paste_bytes,
+            paste_id_length: args.paste_id_length,
+            cleanup_interval: Duration::from_secs(args.cleanup_interval_secs),
+            web_dir: args.web_dir.clone(),
+            max_upload_bytes: args.max_upload_bytes,
+            access: AccessLimits {
+                max_attempts: args.max_access_attempts,
+                policy: args.access_failure_policy,
+                lockout_secs: ACCESS_LOCKOUT.as_secs(),
+            },
+            rate_limits: RateLimits {
+                create: BucketLimit {
+                    per_minute: args.create_rate_limit,
+                    burst: args.create_burst,
+                },
+                read: BucketLimit {
+                    per_minute: args.read_rate_limit,
+                    burst: args.read_burst,
+                },
+                failed_lookup: BucketLimit {
+                    per_minute: args.failed_lookup_rate_limit,
+                    burst: args.failed_lookup_burst,
+                },
+            },
+            trusted_proxies: TrustedProxies::new(args.trusted_proxies.clone()),
+        }
+    }
+
+    /// Checks settings that are only wrong in combination, or that clap
+    /// cannot range-check for `usize`.
+    fn validate(&self) -> Result<(), String> {
+        if self.max_paste_bytes == 0 {
+            return Err("--max-paste-bytes must be at least 1".to_string());
+        }
+        if self.max_paste_bytes > self.capacity.max_total_bytes {
+            return Err(format!(
+                "--max-paste-bytes ({}) exceeds --max-total-bytes ({}), so the largest pastes could never be stored",
+                self.max_paste_bytes, self.capacity.max_total_bytes
+            ));
+        }
+        if !(MIN_PASTE_ID_LENGTH..=MAX_PASTE_ID_LENGTH).contains(&self.paste_id_length) {
+            return Err(format!(
+                "--paste-id-length must be between {} and {}, not {}",
+                MIN_PASTE_ID_LENGTH, MAX_PASTE_ID_LENGTH, self.paste_id_length
+            ));
+        }
+        if self.max_expiry.is_zero() || self.default_expiry.is_zero() {
+            return Err("--max-expiry-secs and --default-expiry-secs must be at least 1".to_string());
+        }
+        if self.default_expiry > self.max_expiry {
+            return Err(format!(
+                "--default-expiry-secs ({}) exceeds --max-expiry-secs ({})",
+                self.default_expiry.as_secs(),
+                self.max_expiry.as_secs()
+            ));
+        }
+        for page in WEB_PAGES {
+            if !self.web_dir.join(page).is_file() {
+                return Err(format!("--web-dir {:?} has no {}", self.web_dir, page));
+            }
+        }
+        Ok(())
+    }
+}
 
 struct AppData {
-    pastes: RwLock<HashMap<String, EncryptedPaste>>,
+    store: Box<dyn PasteStore>,
+    uploads: upload::UploadSessions,
+    rate_limiter: RateLimiter,
+    /// Present when creating pastes requires an API key.
+    api_keys: Option<ApiKeys>,
+    /// Present when new pastes must come with a proof of work.
+    proof_of_work: Option<ProofOfWork>,
+    metrics: Metrics,
+    health: Health,
     config: AppConfig,
 }
 
@@ -79,11 +469,26 @@ type SharedState = Arc<AppData>;
 // --- Main Function ---
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    let args = Args::parse();
+    let args: Args = config::parse();
+    let tracer_provider = args.otlp_endpoint.as_deref().map(|endpoint| {
+        telemetry::init_tracer(endpoint, &args.otlp_service_name, args.otlp_sample_ratio).unwrap_or_else(|e| {
+            eprintln!("Failed to set up OTLP trace export to {}: {}", endpoint, e);
+            std::process::exit(1);
+        })
+    });
+    logging::init(
+        args.log_format,
+        args.log_level.clone(),
+        args.log_paste_ids,
+        !args.no_request_logs,
+        tracer_provider.as_ref(),
+    );
+    if let Some(path) = &args.config {
+        info!("Loaded settings from {:?}", path);
+    }
+    if let Some(endpoint) = &args.otlp_endpoint {
+        info!("Exporting traces to {} as {:?}", endpoint, args.otlp_service_name);
+    }
 
     let (_protocol, tls_config) = match (&args.cert, &args.key) {
         (Some(cert_path), Some(key_path)) => {
@@ -109,9 +514,63 @@ async fn main() {
         }
     };
 
-    let app_config = AppConfig {};
+    let app_config = AppConfig::from_args(&args);
+    if let Err(e) = app_config.validate() {
+        error!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    }
+    let store = match app_config.store_backend.open(app_config.data_dir.as_deref()).await {
+        Ok(store) => store,
+        Err(e) => {
+            error!("Failed to open {:?} paste store: {}", app_config.store_backend, e);
+            std::process::exit(1);
+        }
+    };
+    let api_keys = args.api_keys.as_ref().map(|path| match ApiKeys::load(path) {
+        Ok(keys) => {
+            info!("API keys required for creating pastes: {} key(s) loaded from {:?}", keys.len(), path);
+            keys
+        }
+        Err(e) => {
+            error!("Failed to load API keys from {:?}: {}", path, e);
+            std::process::exit(1);
+        }
+    });
+    let proof_of_work = args.pow_difficulty.map(|base_difficulty| {
+        let config = PowConfig {
+            base_difficulty,
+            max_difficulty: args.pow_max_difficulty.max(base_difficulty),
+            load_threshold: args.pow_load_threshold,
+        };
+        let key = match &args.pow_key_file {
+            Some(path) => match std::fs::read(path) {
+                Ok(secret) if !secret.is_empty() => Sha256::digest(secret).into(),
+                Ok(_) => {
+                    error!("Proof-of-work key file {:?} is empty", path);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    error!("Failed to read proof-of-work key file {:?}: {}", path, e);
+                    std::process::exit(1);
+                }
+            },
+            None => rand::thread_rng().r#gen::<[u8; pow::KEY_LENGTH]>(),
+        };
+        info!(
+            "Proof of work required for new pastes: {} to {} bits, rising with load above {} creates per minute",
+            config.base_difficulty, config.max_difficulty, config.load_threshold
+        );
+        ProofOfWork::new(config, key)
+    });
+    let max_paste_bytes = app_config.max_paste_bytes;
     let app_data = AppData {
-        pastes: RwLock::new(HashMap::new()),
+        store: Box::new(CappedStore::new(store, app_config.capacity)),
+        uploads: upload::UploadSessions::default(),
+        rate_limiter: RateLimiter::new(app_config.rate_limits),
+        api_keys,
+        proof_of_work,
+        metrics: Metrics::new(),
+        health: Health::new(tls_config.is_some(), args.max_memory_bytes),
         config: app_config,
     };
     let shared_state: SharedState = Arc::new(app_data);
@@ -122,75 +581,314 @@ async fn main() {
         delete_expired_pastes(cleanup_state).await;
     });
 
-    // Setup CORS enable if required
-    /*
-    let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([axum::http::header::CONTENT_TYPE])
-        .allow_origin("TODO");*/
-
-    // Define routes.
+    // Define routes. Endpoints that create or look up pastes are rate limited per client IP, and
+    // endpoints that write need an API key when keys are configured.
+    // Upload routes check their framing headers and size before reading any of the body.
+    let create_routes = Router::new()
+        .route(
+            "/create",
+            with_body_policy(post(handle_create_encrypted), BodyPolicy::json(json_body_limit(max_paste_bytes))),
+        )
+        .route("/api/paste", with_body_policy(put(handle_upload_raw), BodyPolicy::binary(max_paste_bytes)))
+        .route(
+            "/api/upload",
+            with_body_policy(post(upload::handle_initiate_upload), BodyPolicy::json(SMALL_JSON_BODY_LIMIT)),
+        )
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), auth::require_api_key))
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), ratelimit::limit_create));
+    let upload_session_routes = Router::new()
+        .route(
+            "/api/upload/:upload_id",
+            get(upload::handle_upload_status).delete(upload::handle_abort_upload),
+        )
+        .route(
+            "/api/upload/:upload_id/chunks/:index",
+            with_body_policy(put(upload::handle_upload_chunk), BodyPolicy::binary(max_paste_bytes)),
+        )
+        .route(
+            "/api/upload/:upload_id/finalize",
+            with_body_policy(post(upload::handle_finalize_upload), BodyPolicy::json(SMALL_JSON_BODY_LIMIT)),
+        )
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), auth::require_api_key));
+    let read_routes = Router::new()
+        .route(
+            "/api/paste/:paste_id",
+            get(handle_get_encrypted_paste).delete(handle_delete_encrypted_paste),
+        )
+        .route("/api/paste/:paste_id/reveal", post(handle_reveal_encrypted_paste))
+        .route("/api/paste/:paste_id/raw", get(handle_get_raw_paste).post(handle_reveal_raw_paste))
+        .route("/api/paste/:paste_id/chunks/:index", get(handle_get_paste_chunk))
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), metrics::count_reads))
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), ratelimit::limit_read));
     let app = Router::new()
         .route("/", get(handle_index))
-        .route("/create", post(handle_create_encrypted))
         .route("/p/*path", get(handle_retrieve_page))
-        .route("/api/paste/:paste_id", get(handle_get_encrypted_paste))
-        .with_state(shared_state);
-        //.layer(cors);
+        .route("/d/*path", get(handle_delete_page))
+        .merge(create_routes)
+        .merge(read_routes)
+        .merge(upload_session_routes)
+        .route("/api/challenge", get(pow::handle_challenge))
+        .route_layer(middleware::from_fn_with_state(shared_state.clone(), metrics::track_requests))
+        .route_layer(middleware::from_fn(telemetry::request_span));
+    // Metrics and store usage stay on the main address unless an admin address is given. Health
+    // checks are on both, since orchestrators usually probe the address they route traffic to.
+    let metrics_routes = Router::new()
+        .route("/metrics", get(metrics::handle_metrics))
+        .route("/api/stats", get(handle_store_stats));
+    let health_routes = Router::new()
+        .route("/healthz", get(health::handle_healthz))
+        .route("/readyz", get(health::handle_readyz));
+    let app = app.merge(health_routes.clone());
+    let app = match args.metrics_addr {
+        Some(metrics_addr) => {
+            let admin_app = metrics_routes.merge(health_routes).with_state(shared_state.clone());
+            let listener = match tokio::net::TcpListener::bind(metrics_addr).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Failed to bind metrics address {}: {}", metrics_addr, e);
+                    std::process::exit(1);
+                }
+            };
+            info!("Serving metrics on http://{}/metrics", metrics_addr);
+            tokio::spawn(async move {
+                axum::serve(listener, admin_app)
+                    .with_graceful_shutdown(shutdown_signal())
+                    .await
+                    .unwrap_or_else(|e| error!("Metrics server failed: {}", e));
+            });
+            app
+        }
+        None => app.merge(metrics_routes),
+    }
+    .with_state(shared_state);
 
     info!("Listening on {}", args.addr);
+    if args.proxy_protocol {
+        let ranges: Vec<String> = args.trusted_proxies.iter().map(Cidr::to_string).collect();
+        info!("Expecting PROXY protocol headers from {}", ranges.join(", "));
+    }
 
-    if let Some(tls_config) = tls_config {
+    // The PROXY protocol header precedes the TLS handshake, so it is read first.
+    let acceptor = ProxyProtocolAcceptor::new(args.proxy_protocol, TrustedProxies::new(args.trusted_proxies));
+    if tls_config.is_some() || args.proxy_protocol {
         let handle = Handle::new();
         let shutdown_handle = handle.clone();
         let shutdown_task = tokio::spawn(async move {
             shutdown_signal().await;
-            info!("Shutdown signal received. Stopping TLS server.");
+            info!("Shutdown signal received. Stopping server.");
             shutdown_handle.shutdown();
         });
 
-        axum_server::bind_rustls(args.addr, tls_config)
-            .handle(handle)
-            .serve(app.clone().into_make_service())
-            .await
-            .unwrap_or_else(|e| error!("HTTPS Server failed: {}", e));
+        let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+        match tls_config {
+            Some(tls_config) => axum_server::bind_rustls(args.addr, tls_config)
+                .map(|tls| tls.acceptor(acceptor))
+                .handle(handle)
+                .serve(make_service)
+                .await
+                .unwrap_or_else(|e| error!("HTTPS Server failed: {}", e)),
+            None => axum_server::bind(args.addr)
+                .acceptor(acceptor)
+                .handle(handle)
+                .serve(make_service)
+                .await
+                .unwrap_or_else(|e| error!("HTTP Server failed: {}", e)),
+        }
 
         // Ensure the shutdown task finishes (ignore cancellation errors)
         let _ = shutdown_task.await;
     } else {
         let listener = tokio::net::TcpListener::bind(args.addr).await.unwrap();
-        axum::serve(listener, app)
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
             .with_graceful_shutdown(shutdown_signal())
             .await
             .unwrap_or_else(|e| error!("HTTP Server failed: {}", e));
     }
     info!("Server shutting down.");
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        error!("Failed to flush traces: {}", e);
+    }
 }
 
 // --- Utility Functions ---
-fn generate_paste_id() -> String {
+fn generate_paste_id(length: usize) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(PASTE_ID_LENGTH)
+        .take(length)
         .map(char::from)
         .collect()
 }
 
+fn generate_deletion_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(DELETION_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn hash_deletion_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn validate_paste_id(paste_id: &str) -> Result<(), StatusCode> {
+    if paste_id.is_empty() || paste_id.len() > MAX_PASTE_ID_LENGTH {
+        warn!("Received get request with invalid paste_id format.");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+fn paste_response(paste: &EncryptedPaste) -> GetEncryptedPasteResponse {
+    GetEncryptedPasteResponse {
+        encrypted_data_b64: base64_engine.encode(&paste.encrypted_data),
+        nonce_b64: base64_engine.encode(&paste.nonce),
+        cipher: paste.cipher(),
+        expires_in_secs: paste.expires_at.saturating_sub(unix_now()),
+        remaining_views: paste.remaining_views,
+        chunk_count: (paste.chunk_count() > 0).then(|| paste.chunk_count()),
+        encrypted_metadata_b64: paste.metadata.as_ref().map(|m| base64_engine.encode(&m.encrypted_data)),
+        metadata_nonce_b64: paste.metadata.as_ref().map(|m| base64_engine.encode(&m.nonce)),
+        items: paste
+            .items
+            .iter()
+            .map(|item| BundleItemPayload {
+                encrypted_data_b64: base64_engine.encode(&item.content.encrypted_data),
+                nonce_b64: base64_engine.encode(&item.content.nonce),
+                encrypted_metadata_b64: item.metadata.as_ref().map(|m| base64_engine.encode(&m.encrypted_data)),
+                metadata_nonce_b64: item.metadata.as_ref().map(|m| base64_engine.encode(&m.nonce)),
+            })
+            .collect(),
+        password_kdf_b64: paste.password_kdf.as_ref().map(|kdf| base64_engine.encode(kdf)),
+    }
+}
+
+/// Raw ciphertext body with the nonce and lifetime in response headers.
+fn raw_paste_response(paste: EncryptedPaste) -> Result<Response, (StatusCode, String)> {
+    if paste.chunk_count() > 0 {
+        return Err((
+            StatusCode::CONFLICT,
+            "This paste was uploaded in chunks; download them from /api/paste/<id>/chunks/<index>.".to_string(),
+        ));
+    }
+    if !paste.items.is_empty() {
+        return Err((
+            StatusCode::CONFLICT,
+            "This paste is a bundle; fetch it as JSON from /api/paste/<id>.".to_string(),
+        ));
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert(EXPIRES_IN_HEADER, HeaderValue::from(paste.expires_at.saturating_sub(unix_now())));
+    if let Some(views) = paste.remaining_views {
+        headers.insert(MAX_VIEWS_HEADER, HeaderValue::from(views));
+    }
+    if let Some(metadata) = &paste.metadata {
+        for (name, bytes) in [(METADATA_HEADER, &metadata.encrypted_data), (METADATA_NONCE_HEADER, &metadata.nonce)] {
+            if let Ok(value) = HeaderValue::from_str(&base64_engine.encode(bytes)) {
+                headers.insert(name, value);
+            }
+        }
+    }
+    if let Some(kdf) = &paste.password_kdf
+        && let Ok(value) = HeaderValue::from_str(&base64_engine.encode(kdf))
+    {
+        headers.insert(PASSWORD_KDF_HEADER, value);
+    }
+    let prefix = envelope::seal_prefix(paste.cipher(), &paste.nonce);
+    if let Ok(value) = HeaderValue::from_str(&base64_engine.encode(prefix)) {
+        headers.insert(ENVELOPE_HEADER, value);
+    }
+    Ok(raw_blob_response(
+        headers,
+        EncryptedBlob {
+            encrypted_data: paste.encrypted_data,
+            nonce: paste.nonce,
+        },
+    ))
+}
+
+fn raw_blob_response(mut headers: HeaderMap, blob: EncryptedBlob) -> Response {
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    if let Ok(nonce) = HeaderValue::from_str(&base64_engine.encode(&blob.nonce)) {
+        headers.insert(NONCE_HEADER, nonce);
+    }
+    (headers, blob.encrypted_data).into_response()
+}
+
+fn parse_numeric_header<T: std::str::FromStr>(
+    headers: &HeaderMap,
+    name: &str,
+) -> Result<Option<T>, (StatusCode, String)> {
+    match headers.get(name) {
+        None => Ok(None),
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .map(Some)
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Invalid {} header", name))),
+    }
+}
+
+/// Picks the lifetime for a new paste: one of the allowed choices, capped at
+/// the server-side maximum.
+fn resolve_expiry(requested: Option<u64>, config: &AppConfig) -> Result<Duration, (StatusCode, String)> {
+    let requested = match requested {
+        None => config.default_expiry,
+        Some(secs) if EXPIRY_CHOICES.contains(&secs) => Duration::from_secs(secs),
+        Some(secs) => {
+            warn!("Received unsupported expiry: {}s", secs);
+            return Err((StatusCode::BAD_REQUEST, format!("Unsupported expiry. Allowed values (seconds): {:?}", EXPIRY_CHOICES)));
+        }
+    };
+    Ok(requested.min(config.max_expiry))
+}
+
 async fn delete_expired_pastes(state: SharedState) {
-    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    let mut interval = tokio::time::interval(state.config.cleanup_interval);
     loop {
         interval.tick().await;
-        info!("Running cleanup task for expired pastes...");
-        let mut pastes = state.pastes.write().await;
-        let now = Instant::now();
-        pastes.retain(|id, paste| {
-            let expired = now.duration_since(paste.timestamp) > EXPIRY_DURATION;
-            if expired {
-                info!("Deleting expired paste with id: {}", id);
-            }
-            !expired
-        });
-        info!("Cleanup finished. Current paste count: {}", pastes.len());
+        state.health.cleanup_ticked();
+        run_cleanup(&state).await;
+    }
+}
+
+/// One pass of the cleanup task, traced as its own root span.
+#[instrument(name = "cleanup", skip_all, fields(pastes.expired = field::Empty))]
+async fn run_cleanup(state: &SharedState) {
+    info!("Running cleanup task for expired pastes...");
+    let abandoned = state.uploads.remove_expired(unix_now()).await;
+    if abandoned > 0 {
+        info!("Dropped {} abandoned upload sessions", abandoned);
+    }
+    let stale = state.rate_limiter.remove_stale();
+    if stale > 0 {
+        info!("Dropped {} idle rate limit buckets", stale);
+    }
+    if let Some(pow) = &state.proof_of_work {
+        let spent = pow.remove_expired(unix_now());
+        if spent > 0 {
+            info!("Forgot {} expired proof-of-work challenges", spent);
+        }
+    }
+    match state.store.delete_expired(unix_now()).await {
+        Ok(expired) => {
+            Span::current().record("pastes.expired", expired);
+            state.metrics.pastes_expired(expired);
+        }
+        Err(e) => {
+            error!("Cleanup failed: {}", e);
+            return;
+        }
+    }
+    match state.store.stats().await {
+        Ok(stats) => info!(
+            "Cleanup finished. Current paste count: {}, bytes: {} ({:?} store)",
+            stats.paste_count, stats.total_bytes, state.config.store_backend
+        ),
+        Err(e) => error!("Failed to read store stats: {}", e),
     }
 }
 
@@ -215,8 +913,8 @@ async fn shutdown_signal() {
     }
 }
 
-async fn read_html_file(filename: &str) -> Result<String, (StatusCode, String)> {
-    let path = PathBuf::from(WEB_DIR).join(filename);
+async fn read_html_file(config: &AppConfig, filename: &str) -> Result<String, (StatusCode, String)> {
+    let path = config.web_dir.join(filename);
     tokio::fs::read_to_string(&path)
         .await
         .map_err(|e| {
@@ -226,14 +924,195 @@ async fn read_html_file(filename: &str) -> Result<String, (StatusCode, String)>
 }
 
 // --- Route Handlers ---
-async fn handle_index() -> Result<Html<String>, (StatusCode, String)> {
-    read_html_file("index.html").await.map(Html)
+async fn handle_index(State(state): State<SharedState>) -> Result<Html<String>, (StatusCode, String)> {
+    read_html_file(&state.config, "index.html").await.map(Html)
 }
 
+#[instrument(skip_all, fields(paste.size = field::Empty))]
 async fn handle_create_encrypted(
     State(state): State<SharedState>,
+    api_key: Option<Extension<Arc<ApiKey>>>,
     Json(payload): Json<CreateEncryptedPasteRequest>,
 ) -> Result<Json<CreateEncryptedPasteResponse>, (StatusCode, String)> {
+    let (cipher, content) = decode_content(&payload)?;
+    Span::current().record("paste.size", content.size());
+    if content.encrypted_data.is_empty() || content.size() > state.config.max_paste_bytes {
+        warn!("Received paste exceeding max size or empty encrypted data");
+        return Err((StatusCode::BAD_REQUEST, "Encrypted content exceeds maximum size limit or is empty".to_string()));
+    }
+    let algorithm = cipher.map(|cipher| cipher.algorithm).unwrap_or_default();
+    let metadata = decode_metadata(
+        payload.encrypted_metadata_b64.as_deref(),
+        payload.metadata_nonce_b64.as_deref(),
+        algorithm,
+    )?;
+    if payload.items.len() > MAX_BUNDLE_ITEMS {
+        warn!("Received bundle with {} items", payload.items.len());
+        return Err((StatusCode::BAD_REQUEST, format!("A bundle may contain at most {} items", MAX_BUNDLE_ITEMS)));
+    }
+    let items = payload
+        .items
+        .iter()
+        .map(|item| decode_bundle_item(item, algorithm))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let new_paste = NewPaste {
+        cipher,
+        content,
+        metadata,
+        items,
+        password_kdf: decode_password_kdf(payload.password_kdf_b64.as_deref())?,
+        access_guard: decode_access_guard(payload.access_token_b64.as_deref())?,
+        ..Default::default()
+    };
+    let total_size = new_paste.content.size() + new_paste.items.iter().map(BundleItem::size).sum::<usize>();
+    if total_size > state.config.max_paste_bytes {
+        warn!("Received bundle exceeding max size");
+        return Err((StatusCode::BAD_REQUEST, "Bundle exceeds maximum size limit".to_string()));
+    }
+    let api_key = api_key.map(|Extension(key)| key);
+    let proof = PowProof {
+        challenge: payload.pow_challenge.as_deref(),
+        nonce: payload.pow_nonce.as_deref(),
+        size: total_size,
+    };
+    let options = PasteOptions {
+        expires_in_secs: payload.expires_in_secs,
+        max_views: payload.max_views,
+    };
+    store_new_paste(&state, new_paste, options, api_key.as_deref(), Some(proof)).await.map(Json)
+}
+
+/// Streams a raw `application/octet-stream` body into a new paste. The nonce
+/// and options travel in headers, and the size limit is enforced as bytes
+/// arrive rather than after the whole body has been buffered.
+async fn handle_upload_raw(
+    State(state): State<SharedState>,
+    api_key: Option<Extension<Arc<ApiKey>>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<CreateEncryptedPasteResponse>, (StatusCode, String)> {
+    let (cipher, nonce) = decode_raw_nonce(&headers)?;
+    let options = PasteOptions {
+        expires_in_secs: parse_numeric_header(&headers, EXPIRES_IN_HEADER)?,
+        max_views: parse_numeric_header(&headers, MAX_VIEWS_HEADER)?,
+    };
+
+    let algorithm = cipher.map(|cipher| cipher.algorithm).unwrap_or_default();
+    let header_str = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+    let metadata = decode_metadata(header_str(METADATA_HEADER), header_str(METADATA_NONCE_HEADER), algorithm)?;
+
+    let mut new_paste = NewPaste {
+        cipher,
+        metadata,
+        password_kdf: decode_password_kdf(header_str(PASSWORD_KDF_HEADER))?,
+        access_guard: decode_access_guard(header_str(ACCESS_TOKEN_HEADER))?,
+        ..Default::default()
+    };
+    // Reject bad options before the body is read.
+    validate_paste_options(&state.config, &new_paste, &options)?;
+
+    let limit = state.config.max_paste_bytes.saturating_sub(nonce.len());
+    let encrypted_data = read_body_limited(body, &headers, limit).await?;
+    let api_key = api_key.map(|Extension(key)| key);
+    let proof = PowProof {
+        challenge: header_str(pow::CHALLENGE_HEADER),
+        nonce: header_str(pow::NONCE_HEADER),
+        size: encrypted_data.len() + nonce.len(),
+    };
+    new_paste.content = EncryptedBlob { encrypted_data, nonce };
+    store_new_paste(&state, new_paste, options, api_key.as_deref(), Some(proof)).await.map(Json)
+}
+
+/// Redeems the proof of work sent with a new paste, if the server asks for
+/// one. Requests with an API key are exempt. Callers run every other check
+/// first, and hand the redemption back to `ProofOfWork::refund` if the paste
+/// is not stored after all, so a rejected request does not use up the proof.
+fn check_proof_of_work(
+    state: &SharedState,
+    api_key: Option<&ApiKey>,
+    proof: &PowProof,
+) -> Result<Option<Redemption>, (StatusCode, String)> {
+    let Some(pow) = state.proof_of_work.as_ref().filter(|_| api_key.is_none()) else {
+        return Ok(None);
+    };
+    let result = match (proof.challenge, proof.nonce) {
+        (Some(challenge), Some(nonce)) => pow.redeem(challenge, nonce, proof.size).map(Some),
+        _ => Err(PowError::Missing),
+    };
+    result.map_err(|e| {
+        warn!("Rejected paste without a valid proof of work: {}", e);
+        let status = match e {
+            PowError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::FORBIDDEN,
+        };
+        (status, e.to_string())
+    })
+}
+
+/// Buffers a streamed request body, failing with 413 as soon as it grows past
+/// `limit` (or immediately if `Content-Length` already says it will).
+async fn read_body_limited(body: Body, headers: &HeaderMap, limit: usize) -> Result<Vec<u8>, (StatusCode, String)> {
+    let declared_len = parse_numeric_header::<usize>(headers, header::CONTENT_LENGTH.as_str())?;
+    if declared_len.is_some_and(|len| len > limit) {
+        warn!("Rejected raw upload with oversized Content-Length");
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "Encrypted content exceeds maximum size limit".to_string()));
+    }
+
+    let mut data = Vec::with_capacity(declared_len.unwrap_or(0));
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            warn!("Raw upload body failed: {}", e);
+            (StatusCode::BAD_REQUEST, "Failed to read request body".to_string())
+        })?;
+        if data.len() + chunk.len() > limit {
+            warn!("Aborted raw upload exceeding max size");
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Encrypted content exceeds maximum size limit".to_string()));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if data.is_empty() {
+        warn!("Received raw upload with empty body");
+        return Err((StatusCode::BAD_REQUEST, "Encrypted content is empty".to_string()));
+    }
+    Ok(data)
+}
+
+fn validate_nonce(algorithm: Algorithm, nonce: &[u8]) -> Result<(), (StatusCode, String)> {
+    algorithm.validate_nonce(nonce).map_err(envelope_error)
+}
+
+fn envelope_error(e: envelope::EnvelopeError) -> (StatusCode, String) {
+    warn!("Received invalid ciphertext envelope: {}", e);
+    (StatusCode::BAD_REQUEST, e.to_string())
+}
+
+/// Decodes the main ciphertext of a JSON upload, either from a versioned
+/// envelope or from a bare AES-256-GCM nonce and ciphertext.
+fn decode_content(
+    payload: &CreateEncryptedPasteRequest,
+) -> Result<(Option<Cipher>, EncryptedBlob), (StatusCode, String)> {
+    if let Some(envelope_b64) = &payload.envelope_b64 {
+        if !payload.encrypted_data_b64.is_empty() || !payload.nonce_b64.is_empty() {
+            warn!("Received both an envelope and a bare nonce and ciphertext");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Send either envelope_b64 or encrypted_data_b64 and nonce_b64, not both".to_string(),
+            ));
+        }
+        let envelope = base64_engine.decode(envelope_b64).map_err(|e| {
+            warn!("Failed to decode envelope base64: {}", e);
+            (StatusCode::BAD_REQUEST, "Invalid envelope encoding".to_string())
+        })?;
+        let (cipher, content) = envelope::open(&envelope).map_err(envelope_error)?;
+        return Ok((Some(cipher), content));
+    }
+    if payload.encrypted_data_b64.is_empty() && payload.nonce_b64.is_empty() {
+        warn!("Received paste without ciphertext");
+        return Err((StatusCode::BAD_REQUEST, "Missing envelope_b64".to_string()));
+    }
+
     // Decode Base64 nonce and encrypted data.
     let nonce = match base64_engine.decode(&payload.nonce_b64) {
         Ok(n) => n,
@@ -249,63 +1128,715 @@ async fn handle_create_encrypted(
             return Err((StatusCode::BAD_REQUEST, "Invalid encrypted_data encoding".to_string()));
         }
     };
+    validate_nonce(Algorithm::default(), &nonce)?;
+    Ok((None, EncryptedBlob { encrypted_data, nonce }))
+}
 
-    if nonce.len() != NONCE_LENGTH {
-        warn!("Received invalid nonce length: {}. Expected: {}", nonce.len(), NONCE_LENGTH);
-        return Err((StatusCode::BAD_REQUEST, format!("Invalid nonce length. Expected {}", NONCE_LENGTH)));
+/// Reads the nonce of a raw upload from either `X-Envelope` or `X-Nonce`.
+fn decode_raw_nonce(headers: &HeaderMap) -> Result<(Option<Cipher>, Vec<u8>), (StatusCode, String)> {
+    let header_str = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+    match (header_str(ENVELOPE_HEADER), header_str(NONCE_HEADER)) {
+        (Some(prefix_b64), None) => {
+            let prefix = base64_engine.decode(prefix_b64).map_err(|e| {
+                warn!("Failed to decode envelope base64: {}", e);
+                (StatusCode::BAD_REQUEST, "Invalid envelope encoding".to_string())
+            })?;
+            let (cipher, nonce) = envelope::open_prefix(&prefix).map_err(envelope_error)?;
+            Ok((Some(cipher), nonce))
+        }
+        (None, Some(nonce_b64)) => {
+            let nonce = base64_engine.decode(nonce_b64).map_err(|e| {
+                warn!("Failed to decode nonce base64: {}", e);
+                (StatusCode::BAD_REQUEST, "Invalid nonce encoding".to_string())
+            })?;
+            validate_nonce(Algorithm::default(), &nonce)?;
+            Ok((None, nonce))
+        }
+        _ => {
+            warn!("Raw upload without exactly one of the envelope and nonce headers");
+            Err((
+                StatusCode::BAD_REQUEST,
+                format!("Send exactly one of the {} and {} headers", ENVELOPE_HEADER, NONCE_HEADER),
+            ))
+        }
     }
-    if encrypted_data.is_empty() || encrypted_data.len() + nonce.len() > MAX_ENCRYPTED_SIZE {
-        warn!("Received paste exceeding max size or empty encrypted data");
-        return Err((StatusCode::BAD_REQUEST, "Encrypted content exceeds maximum size limit or is empty".to_string()));
+}
+
+/// Decodes optional encrypted file metadata. Both parts must be given together.
+fn decode_metadata(
+    encrypted_metadata_b64: Option<&str>,
+    nonce_b64: Option<&str>,
+    algorithm: Algorithm,
+) -> Result<Option<EncryptedBlob>, (StatusCode, String)> {
+    let (encrypted_metadata_b64, nonce_b64) = match (encrypted_metadata_b64, nonce_b64) {
+        (None, None) => return Ok(None),
+        (Some(data), Some(nonce)) => (data, nonce),
+        _ => {
+            warn!("Received encrypted metadata without its nonce or vice versa");
+            return Err((StatusCode::BAD_REQUEST, "Encrypted metadata and its nonce must be sent together".to_string()));
+        }
+    };
+    let decode = |encoded: &str| {
+        base64_engine.decode(encoded).map_err(|e| {
+            warn!("Failed to decode metadata base64: {}", e);
+            (StatusCode::BAD_REQUEST, "Invalid metadata encoding".to_string())
+        })
+    };
+    let metadata = EncryptedBlob {
+        encrypted_data: decode(encrypted_metadata_b64)?,
+        nonce: decode(nonce_b64)?,
+    };
+    if metadata.encrypted_data.is_empty() || metadata.size() > MAX_METADATA_SIZE {
+        warn!("Received encrypted metadata exceeding max size or empty");
+        return Err((StatusCode::BAD_REQUEST, "Encrypted metadata exceeds maximum size limit or is empty".to_string()));
     }
+    validate_nonce(algorithm, &metadata.nonce)?;
+    Ok(Some(metadata))
+}
 
-    let paste_id = generate_paste_id();
-    let paste = EncryptedPaste {
-        encrypted_data,
-        nonce,
-        timestamp: Instant::now(),
+/// Decodes optional passphrase KDF parameters. The server only bounds their
+/// size; their format is up to the client.
+fn decode_password_kdf(password_kdf_b64: Option<&str>) -> Result<Option<Vec<u8>>, (StatusCode, String)> {
+    let Some(password_kdf_b64) = password_kdf_b64 else {
+        return Ok(None);
+    };
+    let password_kdf = base64_engine.decode(password_kdf_b64).map_err(|e| {
+        warn!("Failed to decode password KDF base64: {}", e);
+        (StatusCode::BAD_REQUEST, "Invalid password_kdf encoding".to_string())
+    })?;
+    if password_kdf.is_empty() || password_kdf.len() > MAX_PASSWORD_KDF_SIZE {
+        warn!("Received password KDF parameters exceeding max size or empty");
+        return Err((StatusCode::BAD_REQUEST, "Password KDF parameters exceed maximum size limit or are empty".to_string()));
+    }
+    Ok(Some(password_kdf))
+}
+
+fn decode_access_token(access_token_b64: &str) -> Result<Vec<u8>, (StatusCode, String)> {
+    match base64_engine.decode(access_token_b64) {
+        Ok(token) if token.len() == ACCESS_TOKEN_LENGTH => Ok(token),
+        _ => {
+            warn!("Received malformed access token");
+            Err((StatusCode::BAD_REQUEST, format!("Access token must be {} bytes of base64", ACCESS_TOKEN_LENGTH)))
+        }
+    }
+}
+
+/// Turns the creator's access token into a guard holding only its hash.
+fn decode_access_guard(access_token_b64: Option<&str>) -> Result<Option<AccessGuard>, (StatusCode, String)> {
+    access_token_b64
+        .map(|token| decode_access_token(token).map(|token| AccessGuard::new(Sha256::digest(token).to_vec())))
+        .transpose()
+}
+
+fn decode_bundle_item(item: &BundleItemPayload, algorithm: Algorithm) -> Result<BundleItem, (StatusCode, String)> {
+    let decode = |encoded: &str| {
+        base64_engine.decode(encoded).map_err(|e| {
+            warn!("Failed to decode bundle item base64: {}", e);
+            (StatusCode::BAD_REQUEST, "Invalid bundle item encoding".to_string())
+        })
+    };
+    let content = EncryptedBlob {
+        encrypted_data: decode(&item.encrypted_data_b64)?,
+        nonce: decode(&item.nonce_b64)?,
     };
+    if content.encrypted_data.is_empty() {
+        warn!("Received bundle item with empty encrypted data");
+        return Err((StatusCode::BAD_REQUEST, "Bundle items must not be empty".to_string()));
+    }
+    validate_nonce(algorithm, &content.nonce)?;
+    let metadata = decode_metadata(
+        item.encrypted_metadata_b64.as_deref(),
+        item.metadata_nonce_b64.as_deref(),
+        algorithm,
+    )?;
+    Ok(BundleItem { content, metadata })
+}
 
+/// Checks the creator options against each other and picks the paste's
+/// lifetime and scheme. The chunked upload API runs this before accepting any
+/// chunk, so a bad option never costs the client a whole upload.
+fn validate_paste_options(
+    config: &AppConfig,
+    new_paste: &NewPaste,
+    options: &PasteOptions,
+) -> Result<(Duration, Cipher), (StatusCode, String)> {
+    let expiry = resolve_expiry(options.expires_in_secs, config)?;
+    if new_paste.access_guard.is_some() && new_paste.password_kdf.is_none() {
+        warn!("Received access token for a paste without a passphrase");
+        return Err((StatusCode::BAD_REQUEST, "An access token requires password KDF parameters".to_string()));
+    }
+    let has_passphrase = new_paste.password_kdf.is_some();
+    let cipher = new_paste.cipher.unwrap_or_else(|| Cipher::legacy(has_passphrase));
+    if (cipher.kdf != Kdf::None) != has_passphrase {
+        warn!("Received envelope whose KDF ID does not match the password KDF parameters");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The envelope's KDF ID must be set exactly when password KDF parameters are sent".to_string(),
+        ));
+    }
+    if let Some(views) = options.max_views
+        && (views == 0 || views > MAX_VIEWS_LIMIT)
     {
-        let mut pastes = state.pastes.write().await;
-        if pastes.contains_key(&paste_id) {
-            error!("Paste ID collision detected for ID: {}", paste_id);
+        warn!("Received invalid max_views: {}", views);
+        return Err((StatusCode::BAD_REQUEST, format!("max_views must be between 1 and {}", MAX_VIEWS_LIMIT)));
+    }
+    Ok((expiry, cipher))
+}
+
+/// Validates the creator options and stores a new paste, either as a single
+/// blob or (for finalized chunked uploads) as an ordered list of chunks. Shared
+/// by every upload endpoint; callers validate nonces and sizes first. Pastes
+/// created with an API key count against its quota. `proof` is redeemed last,
+/// right before the insert; chunked uploads pass `None` because they paid
+/// when the session was opened.
+#[instrument(skip_all)]
+async fn store_new_paste(
+    state: &SharedState,
+    new_paste: NewPaste,
+    options: PasteOptions,
+    api_key: Option<&ApiKey>,
+    proof: Option<PowProof<'_>>,
+) -> Result<CreateEncryptedPasteResponse, (StatusCode, String)> {
+    let (expiry, cipher) = validate_paste_options(&state.config, &new_paste, &options)?;
+    let paste_id = generate_paste_id(state.config.paste_id_length);
+    let deletion_token = generate_deletion_token();
+    let now = unix_now();
+    let paste = EncryptedPaste {
+        encrypted_data: new_paste.content.encrypted_data,
+        nonce: new_paste.content.nonce,
+        created_at: now,
+        expires_at: now + expiry.as_secs(),
+        last_read_at: now,
+        remaining_views: options.max_views,
+        deletion_token_hash: Some(hash_deletion_token(&deletion_token)),
+        chunks: new_paste.chunks,
+        unloaded_chunks: 0,
+        metadata: new_paste.metadata,
+        items: Arc::new(new_paste.items),
+        password_kdf: new_paste.password_kdf,
+        access_guard: new_paste.access_guard,
+        cipher: Some(cipher),
+    };
+
+    let size = paste.size();
+    if let Some(key) = api_key {
+        key.reserve(size).map_err(|retry_after| auth::quota_exceeded(key, retry_after))?;
+    }
+    let redemption = match proof.map(|proof| check_proof_of_work(state, api_key, &proof)).transpose() {
+        Ok(redemption) => redemption.flatten(),
+        Err(e) => {
+            if let Some(key) = api_key {
+                key.release(size);
+            }
+            return Err(e);
+        }
+    };
+    let stored = state.store.insert(paste_id.clone(), paste).await;
+    if stored.is_err() {
+        if let Some(key) = api_key {
+            key.release(size);
+        }
+        if let (Some(pow), Some(redemption)) = (&state.proof_of_work, redemption) {
+            pow.refund(redemption);
+        }
+    }
+    match stored {
+        Ok(()) => {}
+        Err(StoreError::Conflict) => {
+            error!("Paste ID collision detected for ID: {}", logging::paste_id(&paste_id));
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Could not save paste, please try again.".to_string()));
+        }
+        Err(StoreError::Full) => {
+            return Err((StatusCode::INSUFFICIENT_STORAGE, "Server storage is full, please try again later.".to_string()));
+        }
+        Err(e) => {
+            error!("Failed to store paste {}: {}", logging::paste_id(&paste_id), e);
             return Err((StatusCode::INTERNAL_SERVER_ERROR, "Could not save paste, please try again.".to_string()));
         }
-        pastes.insert(paste_id.clone(), paste);
     }
 
-    info!("Stored encrypted paste with id: {}", paste_id);
-    Ok(Json(CreateEncryptedPasteResponse { paste_id }))
+    state.metrics.paste_created();
+    match api_key {
+        Some(key) => info!(
+            "Stored encrypted paste with id: {} (expires in {}s) for API key {:?}",
+            logging::paste_id(&paste_id),
+            expiry.as_secs(),
+            key.label
+        ),
+        None => info!("Stored encrypted paste with id: {} (expires in {}s)", logging::paste_id(&paste_id), expiry.as_secs()),
+    }
+    Ok(CreateEncryptedPasteResponse {
+        paste_id,
+        expires_in_secs: expiry.as_secs(),
+        deletion_token,
+    })
 }
 
-async fn handle_retrieve_page() -> Result<Html<String>, (StatusCode, String)> {
-    read_html_file("retrieve.html").await.map(Html)
+async fn handle_retrieve_page(State(state): State<SharedState>) -> Result<Html<String>, (StatusCode, String)> {
+    read_html_file(&state.config, "retrieve.html").await.map(Html)
 }
 
+#[instrument(skip_all)]
 async fn handle_get_encrypted_paste(
     State(state): State<SharedState>,
     Path(paste_id): Path<String>,
-) -> Result<Json<GetEncryptedPasteResponse>, StatusCode> {
-    if paste_id.is_empty() || paste_id.len() > 50 {
-        warn!("Received get request with invalid paste_id format.");
-        return Err(StatusCode::BAD_REQUEST);
+    headers: HeaderMap,
+) -> Result<Json<GetPasteResponse>, (StatusCode, String)> {
+    validate_paste_id(&paste_id).map_err(|status| (status, "Invalid paste ID".to_string()))?;
+
+    info!("Attempting retrieval for paste id: {}", logging::paste_id(&paste_id));
+    let access = check_access(&state, &paste_id, &headers).await?;
+    let paste = state.store.get(&paste_id).await.map_err(|e| {
+        error!("Failed to read paste {}: {}", logging::paste_id(&paste_id), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string())
+    })?;
+    match paste {
+        // View-limited pastes are never released by a plain GET, so link
+        // previews and crawlers cannot burn them. Guarded pastes only reveal
+        // their KDF parameters until a valid access token is presented.
+        Some(paste) if paste.remaining_views.is_some() || access == Access::TokenMissing => {
+            info!("Paste {} requires an explicit reveal or access token", logging::paste_id(&paste_id));
+            Ok(Json(GetPasteResponse::Sealed(SealedPasteResponse {
+                reveal_required: paste.remaining_views.is_some(),
+                access_required: access == Access::TokenMissing,
+                expires_in_secs: paste.expires_at.saturating_sub(unix_now()),
+                remaining_views: paste.remaining_views,
+                cipher: paste.cipher(),
+                password_kdf_b64: paste.password_kdf.as_ref().map(|kdf| base64_engine.encode(kdf)),
+            })))
+        }
+        Some(paste) => {
+            info!("Returning encrypted data for id: {}", logging::paste_id(&paste_id));
+            Ok(Json(GetPasteResponse::Paste(paste_response(&paste))))
+        }
+        None => {
+            warn!("Paste not found for id: {}", logging::paste_id(&paste_id));
+            Err((StatusCode::NOT_FOUND, "Paste not found".to_string()))
+        }
     }
+}
+
+async fn handle_reveal_encrypted_paste(
+    State(state): State<SharedState>,
+    Path(paste_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<GetEncryptedPasteResponse>, (StatusCode, String)> {
+    validate_paste_id(&paste_id).map_err(|status| (status, "Invalid paste ID".to_string()))?;
 
-    info!("Attempting retrieval for paste id: {}", paste_id);
-    let pastes = state.pastes.read().await;
-    match pastes.get(&paste_id) {
+    info!("Attempting reveal for paste id: {}", logging::paste_id(&paste_id));
+    require_access(&state, &paste_id, &headers).await?;
+    let paste = state.store.take_view(&paste_id).await.map_err(|e| {
+        error!("Failed to read paste {}: {}", logging::paste_id(&paste_id), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string())
+    })?;
+    match paste {
         Some(paste) => {
-            let response = GetEncryptedPasteResponse {
-                encrypted_data_b64: base64_engine.encode(&paste.encrypted_data),
-                nonce_b64: base64_engine.encode(&paste.nonce),
-            };
-            info!("Returning encrypted data for id: {}", paste_id);
-            Ok(Json(response))
+            info!("Revealing encrypted data for id: {}", logging::paste_id(&paste_id));
+            Ok(Json(paste_response(&paste)))
         }
         None => {
-            warn!("Paste not found for id: {}", paste_id);
-            Err(StatusCode::NOT_FOUND)
+            warn!("Paste not found for id: {}", logging::paste_id(&paste_id));
+            Err((StatusCode::NOT_FOUND, "Paste not found".to_string()))
+        }
+    }
+}
+
+async fn handle_get_raw_paste(
+    State(state): State<SharedState>,
+    Path(paste_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    validate_paste_id(&paste_id).map_err(|status| (status, "Invalid paste ID".to_string()))?;
+
+    info!("Attempting raw retrieval for paste id: {}", logging::paste_id(&paste_id));
+    require_access(&state, &paste_id, &headers).await?;
+    match state.store.get(&paste_id).await {
+        Ok(Some(paste)) if paste.remaining_views.is_some() => Err((
+            StatusCode::CONFLICT,
+            "This paste has a view limit; reveal it with POST to this URL.".to_string(),
+        )),
+        Ok(Some(paste)) => raw_paste_response(paste),
+        Ok(None) => {
+            warn!("Paste not found for id: {}", logging::paste_id(&paste_id));
+            Err((StatusCode::NOT_FOUND, "Paste not found".to_string()))
+        }
+        Err(e) => {
+            error!("Failed to read paste {}: {}", logging::paste_id(&paste_id), e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string()))
+        }
+    }
+}
+
+async fn handle_reveal_raw_paste(
+    State(state): State<SharedState>,
+    Path(paste_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    validate_paste_id(&paste_id).map_err(|status| (status, "Invalid paste ID".to_string()))?;
+
+    info!("Attempting raw reveal for paste id: {}", logging::paste_id(&paste_id));
+    require_access(&state, &paste_id, &headers).await?;
+    match state.store.take_view(&paste_id).await {
+        Ok(Some(paste)) => raw_paste_response(paste),
+        Ok(None) => {
+            warn!("Paste not found for id: {}", logging::paste_id(&paste_id));
+            Err((StatusCode::NOT_FOUND, "Paste not found".to_string()))
+        }
+        Err(e) => {
+            error!("Failed to read paste {}: {}", logging::paste_id(&paste_id), e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string()))
+        }
+    }
+}
+
+async fn handle_get_paste_chunk(
+    State(state): State<SharedState>,
+    Path((paste_id, index)): Path<(String, usize)>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    validate_paste_id(&paste_id).map_err(|status| (status, "Invalid paste ID".to_string()))?;
+    require_access(&state, &paste_id, &headers).await?;
+
+    match state.store.get_chunk(&paste_id, index).await {
+        Ok(Some(chunk)) => Ok(raw_blob_response(HeaderMap::new(), chunk)),
+        Ok(None) => {
+            warn!("Chunk {} not found for paste id: {}", index, logging::paste_id(&paste_id));
+            Err((StatusCode::NOT_FOUND, "Chunk not found".to_string()))
+        }
+        Err(e) => {
+            error!("Failed to read chunk {} of paste {}: {}", index, logging::paste_id(&paste_id), e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string()))
+        }
+    }
+}
+
+// --- Access Guards ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    /// The paste is not guarded (or does not exist).
+    Open,
+    /// A valid access token was presented.
+    Granted,
+    /// The paste is guarded and the request carried no access token.
+    TokenMissing,
+}
+
+/// Verifies the `X-Access-Token` header against a guarded paste. Wrong tokens
+/// are counted, and once the limit is reached the paste is locked or burned.
+async fn check_access(
+    state: &SharedState,
+    paste_id: &str,
+    headers: &HeaderMap,
+) -> Result<Access, (StatusCode, String)> {
+    let guard = state.store.access_guard(paste_id).await.map_err(|e| {
+        error!("Failed to read access guard for paste {}: {}", logging::paste_id(paste_id), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string())
+    })?;
+    let Some(guard) = guard else {
+        return Ok(Access::Open);
+    };
+    let now = unix_now();
+    if guard.is_locked(now) {
+        warn!("Refusing access to locked paste {}", logging::paste_id(paste_id));
+        return Err(locked_error(guard.locked_until, now));
+    }
+    let Some(token_b64) = headers.get(ACCESS_TOKEN_HEADER).and_then(|v| v.to_str().ok()) else {
+        return Ok(Access::TokenMissing);
+    };
+    let token = decode_access_token(token_b64)?;
+    if bool::from(guard.token_hash.ct_eq(&Sha256::digest(&token))) {
+        return Ok(Access::Granted);
+    }
+
+    warn!("Wrong access token for paste {}", logging::paste_id(paste_id));
+    let failure = state
+        .store
+        .record_failed_access(paste_id, now, state.config.access)
+        .await
+        .map_err(|e| {
+            error!("Failed to record access failure for paste {}: {}", logging::paste_id(paste_id), e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not read paste".to_string())
+        })?;
+    Err(match failure {
+        Some(AccessFailure::AttemptsLeft(left)) => {
+            (StatusCode::FORBIDDEN, format!("Wrong passphrase. {} attempt(s) left.", left))
+        }
+        Some(AccessFailure::LockedUntil(until)) => locked_error(until, now),
+        Some(AccessFailure::Burned) => {
+            info!("Burned paste {} after too many wrong access tokens", logging::paste_id(paste_id));
+            (StatusCode::GONE, "Too many wrong passphrases; the paste has been deleted.".to_string())
+        }
+        None => (StatusCode::NOT_FOUND, "Paste not found".to_string()),
+    })
+}
+
+/// Like [`check_access`], but rejects guarded pastes without a token.
+async fn require_access(state: &SharedState, paste_id: &str, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    match check_access(state, paste_id, headers).await? {
+        Access::TokenMissing => Err((
+            StatusCode::UNAUTHORIZED,
+            format!("This paste is protected; send the access token in the {} header.", ACCESS_TOKEN_HEADER),
+        )),
+        Access::Open | Access::Granted => Ok(()),
+    }
+}
+
+fn locked_error(locked_until: u64, now: u64) -> (StatusCode, String) {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        format!(
+            "Too many wrong passphrases. Try again in {} seconds.",
+            locked_until.saturating_sub(now)
+        ),
+    )
+}
+
+async fn handle_delete_page(State(state): State<SharedState>) -> Result<Html<String>, (StatusCode, String)> {
+    read_html_file(&state.config, "delete.html").await.map(Html)
+}
+
+async fn handle_delete_encrypted_paste(
+    State(state): State<SharedState>,
+    Path(paste_id): Path<String>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if let Err(status) = validate_paste_id(&paste_id) {
+        return status;
+    }
+    let Some(token) = headers.get(DELETION_TOKEN_HEADER).and_then(|v| v.to_str().ok()) else {
+        warn!("Delete request without deletion token for id: {}", logging::paste_id(&paste_id));
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    let paste = match state.store.get(&paste_id).await {
+        Ok(Some(paste)) => paste,
+        Ok(None) => {
+            warn!("Paste not found for deletion, id: {}", logging::paste_id(&paste_id));
+            return StatusCode::NOT_FOUND;
+        }
+        Err(e) => {
+            error!("Failed to read paste {}: {}", logging::paste_id(&paste_id), e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let authorized = paste
+        .deletion_token_hash
+        .as_deref()
+        .is_some_and(|expected| bool::from(expected.ct_eq(&hash_deletion_token(token))));
+    if !authorized {
+        warn!("Invalid deletion token for id: {}", logging::paste_id(&paste_id));
+        return StatusCode::FORBIDDEN;
+    }
+
+    match state.store.delete(&paste_id).await {
+        Ok(true) => {
+            info!("Deleted paste on creator request, id: {}", logging::paste_id(&paste_id));
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Failed to delete paste {}: {}", logging::paste_id(&paste_id), e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn handle_store_stats(
+    State(state): State<SharedState>,
+) -> Result<Json<StoreStatsResponse>, StatusCode> {
+    let stats = state.store.stats().await.map_err(|e| {
+        error!("Failed to read store stats: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(StoreStatsResponse {
+        paste_count: stats.paste_count,
+        total_bytes: stats.total_bytes,
+        max_pastes: state.config.capacity.max_pastes,
+        max_total_bytes: state.config.capacity.max_total_bytes,
+    }))
+}
+
+// --- Request Body Limits ---
+/// What an upload route accepts, checked from the request headers alone so
+/// bad uploads are refused before any of their body is read.
+#[derive(Clone, Copy)]
+struct BodyPolicy {
+    limit: usize,
+    content_type: &'static str,
+}
+
+impl BodyPolicy {
+    fn json(limit: usize) -> Self {
+        Self {
+            limit,
+            content_type: "application/json",
+        }
+    }
+
+    fn binary(limit: usize) -> Self {
+        Self {
+            limit,
+            content_type: "application/octet-stream",
+        }
+    }
+}
+
+/// Largest JSON body that can carry a paste of `max_encrypted_size` bytes:
+/// base64 grows the ciphertext by a third, plus room for everything around it.
+fn json_body_limit(max_encrypted_size: usize) -> usize {
+    max_encrypted_size.div_ceil(3) * 4 + JSON_BODY_OVERHEAD
+}
+
+fn with_body_policy(route: MethodRouter<SharedState>, policy: BodyPolicy) -> MethodRouter<SharedState> {
+    let route: MethodRouter<SharedState> = route.layer(middleware::from_fn_with_state(policy, check_body_headers));
+    route.layer(DefaultBodyLimit::max(policy.limit))
+}
+
+/// Requires a matching `Content-Type` (415) and a `Content-Length` (411) no
+/// larger than the route's limit (413). Hyper guarantees the body then has
+/// exactly that length.
+async fn check_body_headers(
+    State(policy): State<BodyPolicy>,
+    request: Request,
+    next: Next,
+) -> Result<Response, (StatusCode, String)> {
+    let headers = request.headers();
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::trim);
+    if !content_type.is_some_and(|v| v.eq_ignore_ascii_case(policy.content_type)) {
+        warn!("Rejected upload with Content-Type {:?}", content_type);
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Expected Content-Type: {}", policy.content_type),
+        ));
+    }
+    let Some(content_length) = parse_numeric_header::<usize>(headers, header::CONTENT_LENGTH.as_str())? else {
+        warn!("Rejected upload without Content-Length");
+        return Err((StatusCode::LENGTH_REQUIRED, "Content-Length header is required".to_string()));
+    };
+    if content_length > policy.limit {
+        warn!("Rejected upload with Content-Length {} over the {} byte limit", content_length, policy.limit);
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body exceeds the {} byte limit", policy.limit),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+// --- Test Support ---
+/// State for handler tests: the defaults plus `cli`, over a memory store.
+/// Proof-of-work challenges are signed with a fixed key and ignore load.
+#[cfg(test)]
+fn test_state(cli: &[&str]) -> SharedState {
+    let args = Args::parse_from(std::iter::once("rsDrop").chain(cli.iter().copied()));
+    let config = AppConfig::from_args(&args);
+    config.validate().expect("valid test configuration");
+    let proof_of_work = args.pow_difficulty.map(|base_difficulty| {
+        let pow_config = PowConfig {
+            base_difficulty,
+            max_difficulty: args.pow_max_difficulty.max(base_difficulty),
+            load_threshold: 0,
+        };
+        ProofOfWork::new(pow_config, [7; pow::KEY_LENGTH])
+    });
+    Arc::new(AppData {
+        store: Box::new(CappedStore::new(Box::new(store::MemoryStore::new()), config.capacity)),
+        uploads: upload::UploadSessions::default(),
+        rate_limiter: RateLimiter::new(config.rate_limits),
+        api_keys: args.api_keys.as_ref().map(|path| ApiKeys::load(path).expect("valid test key file")),
+        proof_of_work,
+        metrics: Metrics::new(),
+        health: Health::new(false, None),
+        config,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderName;
+    use serde_json::json;
+
+    const NONCE_B64: &str = "YWJjZGVmZ2hpamts"; // 12 bytes, as AES-256-GCM expects
+
+    /// A `/create` body with a solved proof of work.
+    fn create_request(state: &SharedState) -> serde_json::Value {
+        let (challenge, solution) = state.proof_of_work.as_ref().unwrap().issue_solved(1024);
+        json!({
+            "encrypted_data_b64": base64_engine.encode([0xcc; 32]),
+            "nonce_b64": NONCE_B64,
+            "pow_challenge": challenge,
+            "pow_nonce": solution,
+        })
+    }
+
+    async fn create(state: &SharedState, request: &serde_json::Value) -> Result<String, StatusCode> {
+        let request = serde_json::from_value(request.clone()).unwrap();
+        handle_create_encrypted(State(state.clone()), None, Json(request))
+            .await
+            .map(|Json(response)| response.paste_id)
+            .map_err(|(status, _)| status)
+    }
+
+    async fn upload_raw(state: &SharedState, headers: &[(&str, &str)], body: &[u8]) -> Result<String, StatusCode> {
+        let mut header_map = HeaderMap::new();
+        header_map.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+        header_map.insert(NONCE_HEADER, HeaderValue::from_static(NONCE_B64));
+        for (name, value) in headers {
+            header_map.insert(HeaderName::from_bytes(name.as_bytes()).unwrap(), HeaderValue::from_str(value).unwrap());
         }
+        handle_upload_raw(State(state.clone()), None, header_map, Body::from(body.to_vec()))
+            .await
+            .map(|Json(response)| response.paste_id)
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn rejected_options_do_not_use_up_the_proof() {
+        let state = test_state(&["--pow-difficulty", "4"]);
+        let mut request = create_request(&state);
+        request["max_views"] = json!(0);
+        assert_eq!(create(&state, &request).await, Err(StatusCode::BAD_REQUEST));
+        request["expires_in_secs"] = json!(0);
+        request["max_views"] = json!(1);
+        assert_eq!(create(&state, &request).await, Err(StatusCode::BAD_REQUEST));
+        request["expires_in_secs"] = json!(null);
+        assert!(create(&state, &request).await.is_ok());
+        assert_eq!(create(&state, &request).await, Err(StatusCode::FORBIDDEN), "a stored paste spends the proof");
+    }
+
+    #[tokio::test]
+    async fn full_store_does_not_use_up_the_proof() {
+        let state = test_state(&["--pow-difficulty", "4", "--max-pastes", "1"]);
+        let first = create(&state, &create_request(&state)).await.unwrap();
+        let request = create_request(&state);
+        assert_eq!(create(&state, &request).await, Err(StatusCode::INSUFFICIENT_STORAGE));
+        assert!(state.store.delete(&first).await.unwrap());
+        assert!(create(&state, &request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_proof_is_rejected() {
+        let state = test_state(&["--pow-difficulty", "4"]);
+        let mut request = create_request(&state);
+        request["pow_nonce"] = json!(null);
+        assert_eq!(create(&state, &request).await, Err(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn raw_upload_redeems_the_proof_last() {
+        let state = test_state(&["--pow-difficulty", "4", "--max-paste-bytes", "1024"]);
+        let (challenge, solution) = state.proof_of_work.as_ref().unwrap().issue_solved(1024);
+        let proof = [(pow::CHALLENGE_HEADER, challenge.as_str()), (pow::NONCE_HEADER, solution.as_str())];
+
+        let bad_views = [proof[0], proof[1], (MAX_VIEWS_HEADER, "0")];
+        assert_eq!(upload_raw(&state, &bad_views, &[0xcc; 32]).await, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(upload_raw(&state, &proof, &[0xcc; 2048]).await, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        assert!(upload_raw(&state, &proof, &[0xcc; 32]).await.is_ok());
+        assert_eq!(upload_raw(&state, &proof, &[0xcc; 32]).await, Err(StatusCode::FORBIDDEN));
     }
 }
@@ -0,0 +1,171 @@
+use crate::SharedState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::Instant;
+use tracing::error;
+
+// --- Configuration Constants ---
+const NAMESPACE: &str = "rsdrop";
+// Seconds; uploads of several MiB over slow links land in the upper buckets.
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+const CHUNK_ROUTE_SUFFIX: &str = "/chunks/:index";
+
+// --- Data Structures ---
+/// Prometheus metrics of one server. Nothing that identifies a paste is
+/// recorded; routes are labelled by their pattern, not the requested path.
+pub struct Metrics {
+    registry: Registry,
+    pastes_created: IntCounter,
+    paste_reads: IntCounter,
+    paste_not_found: IntCounter,
+    rejections: IntCounterVec,
+    pastes_expired: IntCounter,
+    pastes: IntGauge,
+    stored_bytes: IntGauge,
+    request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None).expect("valid metrics namespace");
+        let metrics = Self {
+            pastes_created: IntCounter::new("pastes_created_total", "Pastes stored by any upload endpoint")
+                .expect("valid metric"),
+            paste_reads: IntCounter::new("paste_reads_total", "Pastes served to readers, not counting chunk downloads")
+                .expect("valid metric"),
+            paste_not_found: IntCounter::new("paste_not_found_total", "Paste lookups that found no paste")
+                .expect("valid metric"),
+            rejections: IntCounterVec::new(
+                Opts::new("rejections_total", "Requests refused, by reason derived from the status code"),
+                &["reason"],
+            )
+            .expect("valid metric"),
+            pastes_expired: IntCounter::new("pastes_expired_total", "Expired pastes removed by the cleanup task")
+                .expect("valid metric"),
+            pastes: IntGauge::new("pastes", "Pastes currently stored").expect("valid metric"),
+            stored_bytes: IntGauge::new("stored_bytes", "Bytes currently stored across all pastes")
+                .expect("valid metric"),
+            request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "Request latency by route pattern")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["route", "method"],
+            )
+            .expect("valid metric"),
+            registry,
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 8] = [
+            Box::new(metrics.pastes_created.clone()),
+            Box::new(metrics.paste_reads.clone()),
+            Box::new(metrics.paste_not_found.clone()),
+            Box::new(metrics.rejections.clone()),
+            Box::new(metrics.pastes_expired.clone()),
+            Box::new(metrics.pastes.clone()),
+            Box::new(metrics.stored_bytes.clone()),
+            Box::new(metrics.request_duration.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric registered once");
+        }
+        metrics
+    }
+
+    pub fn paste_created(&self) {
+        self.pastes_created.inc();
+    }
+
+    pub fn pastes_expired(&self, count: usize) {
+        self.pastes_expired.inc_by(count as u64);
+    }
+}
+
+/// Label for a refused request. Reasons that share a status code, such as
+/// rate limits and API key quotas, share a label.
+fn rejection_reason(status: StatusCode) -> Option<&'static str> {
+    let reason = match status {
+        StatusCode::BAD_REQUEST => "invalid_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::GONE => "gone",
+        StatusCode::LENGTH_REQUIRED => "length_required",
+        StatusCode::PAYLOAD_TOO_LARGE => "too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_request",
+        StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        StatusCode::INSUFFICIENT_STORAGE => "storage_full",
+        // Missing pastes are counted on their own; other 404s are routine.
+        StatusCode::NOT_FOUND => return None,
+        status if status.is_server_error() => "server_error",
+        status if status.is_client_error() => "other",
+        _ => return None,
+    };
+    Some(reason)
+}
+
+// --- Middleware ---
+/// Records the latency of every routed request and counts refusals.
+pub async fn track_requests(
+    State(state): State<SharedState>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = matched_path.as_ref().map_or("unmatched", MatchedPath::as_str).to_string();
+    let method = request.method().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let metrics = &state.metrics;
+    metrics
+        .request_duration
+        .with_label_values(&[&route, method.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    if let Some(reason) = rejection_reason(response.status()) {
+        metrics.rejections.with_label_values(&[reason]).inc();
+    }
+    response
+}
+
+/// Counts reads and missing pastes. Applied to the paste lookup routes.
+pub async fn count_reads(
+    State(state): State<SharedState>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_chunk = matched_path.is_some_and(|path| path.as_str().ends_with(CHUNK_ROUTE_SUFFIX));
+    let is_delete = request.method() == Method::DELETE;
+    let response = next.run(request).await;
+    if response.status() == StatusCode::NOT_FOUND {
+        state.metrics.paste_not_found.inc();
+    } else if response.status().is_success() && !is_chunk && !is_delete {
+        state.metrics.paste_reads.inc();
+    }
+    response
+}
+
+// --- Route Handlers ---
+pub async fn handle_metrics(State(state): State<SharedState>) -> Response {
+    let metrics = &state.metrics;
+    match state.store.stats().await {
+        Ok(stats) => {
+            metrics.pastes.set(stats.paste_count as i64);
+            metrics.stored_bytes.set(stats.total_bytes as i64);
+        }
+        Err(e) => error!("Failed to read store stats for metrics: {}", e),
+    }
+    let mut body = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&metrics.registry.gather(), &mut body) {
+        error!("Failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    let content_type = HeaderValue::from_static("text/plain; version=0.0.4");
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
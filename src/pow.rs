@@ -0,0 +1,491 @@
+use crate::{json_body_limit, store::unix_now, SharedState};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as base64_url, Engine as _};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// --- Configuration Constants ---
+pub const DEFAULT_MAX_DIFFICULTY: u8 = 24;
+pub const DEFAULT_LOAD_THRESHOLD: u32 = 30; // Creates per minute before difficulty starts rising
+pub const CHALLENGE_HEADER: &str = "x-pow-challenge"; // Proof for raw uploads, instead of JSON fields
+pub const NONCE_HEADER: &str = "x-pow-nonce";
+pub const KEY_LENGTH: usize = 32;
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+const SIZE_STEP: usize = 1024 * 1024; // One extra bit per doubling of the paste size above 1 MiB
+const MAX_SOLUTION_LENGTH: usize = 64;
+const MAX_SPENT_CHALLENGES: usize = 100_000;
+
+// --- Challenge Format ---
+// A challenge is self-describing and signed, so the server keeps no state
+// until it is redeemed. Base64url (no padding) of:
+//
+//   offset  size  field
+//   0       1     version (1)
+//   1       8     expiry, Unix seconds, big-endian
+//   9       1     difficulty: leading zero bits required
+//   10      8     largest paste size in bytes the proof covers, big-endian
+//   18      16    random salt
+//   34      32    HMAC-SHA256 of the bytes above
+//
+// A solution is any string of up to 64 characters for which
+// SHA-256("<challenge>:<solution>") starts with `difficulty` zero bits.
+const CHALLENGE_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const SIGNED_LENGTH: usize = 1 + 8 + 1 + 8 + SALT_LENGTH;
+const MAC_LENGTH: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// --- Data Structures ---
+/// How hard challenges are. Difficulty is counted in leading zero bits, so
+/// each extra bit doubles the expected work.
+#[derive(Clone, Copy, Debug)]
+pub struct PowConfig {
+    pub base_difficulty: u8,
+    pub max_difficulty: u8,
+    /// Creates per minute above which difficulty rises by one bit per
+    /// doubling of the rate; zero disables load scaling.
+    pub load_threshold: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PowError {
+    Missing,
+    Malformed,
+    BadSignature,
+    Expired,
+    TooSmall { max_size: u64 },
+    Unsolved,
+    Spent,
+    /// Too many unexpired challenges were redeemed to remember another one.
+    Busy,
+}
+
+impl fmt::Display for PowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "Proof of work required. Fetch a challenge from /api/challenge"),
+            Self::Malformed => write!(f, "Malformed proof-of-work challenge"),
+            Self::BadSignature => write!(f, "Invalid proof-of-work challenge"),
+            Self::Expired => write!(f, "Proof-of-work challenge expired. Fetch a new one"),
+            Self::TooSmall { max_size } => {
+                write!(f, "Proof-of-work challenge only covers {} bytes. Fetch one for the full size", max_size)
+            }
+            Self::Unsolved => write!(f, "Proof-of-work solution does not meet the difficulty"),
+            Self::Spent => write!(f, "Proof-of-work challenge was already used"),
+            Self::Busy => write!(f, "Too many pastes are being created. Try again in a few minutes"),
+        }
+    }
+}
+
+/// Fields of a verified challenge.
+struct Claims {
+    expires_at: u64,
+    difficulty: u8,
+    max_size: u64,
+    salt: [u8; SALT_LENGTH],
+}
+
+/// A redeemed challenge. Handing it back with [`ProofOfWork::refund`] makes
+/// the proof usable again, for when the paste it paid for was not stored.
+#[must_use]
+pub struct Redemption {
+    salt: [u8; SALT_LENGTH],
+}
+
+/// Creates seen in the current and previous minute, for a sliding estimate
+/// of the create rate. Only redeemed challenges count: fetching a challenge
+/// costs nothing, so counting those would let anyone raise the difficulty
+/// for every client, while a flood of fetches is left to the rate limiter.
+struct LoadMeter {
+    window_start: Instant,
+    current: u32,
+    previous: u32,
+}
+
+/// Issues and redeems proof-of-work challenges. Only redeemed challenges are
+/// remembered, until they expire, so each solution is good for one paste. At
+/// most `MAX_SPENT_CHALLENGES` are remembered; beyond that, redeeming fails
+/// until older ones expire.
+pub struct ProofOfWork {
+    config: PowConfig,
+    key: [u8; KEY_LENGTH],
+    load: Mutex<LoadMeter>,
+    spent: Mutex<HashMap<[u8; SALT_LENGTH], u64>>,
+}
+
+impl ProofOfWork {
+    pub fn new(config: PowConfig, key: [u8; KEY_LENGTH]) -> Self {
+        Self {
+            config,
+            key,
+            load: Mutex::new(LoadMeter {
+                window_start: Instant::now(),
+                current: 0,
+                previous: 0,
+            }),
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Difficulty for a paste of `size` bytes at the current create rate.
+    pub fn difficulty(&self, size: usize) -> u8 {
+        let size_bits = size.div_ceil(SIZE_STEP).max(1).next_power_of_two().trailing_zeros();
+        let load_bits = match self.config.load_threshold {
+            0 => 0,
+            threshold => {
+                let ratio = self.creates_per_minute() / f64::from(threshold);
+                if ratio < 1.0 { 0 } else { ratio.log2() as u32 + 1 }
+            }
+        };
+        let difficulty = u32::from(self.config.base_difficulty) + size_bits + load_bits;
+        difficulty.min(u32::from(self.config.max_difficulty)) as u8
+    }
+
+    pub fn issue(&self, size: usize) -> ChallengeResponse {
+        let difficulty = self.difficulty(size);
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let mut challenge = Vec::with_capacity(SIGNED_LENGTH + MAC_LENGTH);
+        challenge.push(CHALLENGE_VERSION);
+        challenge.extend_from_slice(&(unix_now() + CHALLENGE_TTL.as_secs()).to_be_bytes());
+        challenge.push(difficulty);
+        challenge.extend_from_slice(&(size as u64).to_be_bytes());
+        challenge.extend_from_slice(&salt);
+        let mac = self.mac(&challenge).finalize().into_bytes();
+        challenge.extend_from_slice(&mac);
+        ChallengeResponse {
+            required: true,
+            challenge: Some(base64_url.encode(challenge)),
+            difficulty: Some(difficulty),
+            max_size: Some(size),
+            expires_in_secs: Some(CHALLENGE_TTL.as_secs()),
+        }
+    }
+
+    /// Checks a solved challenge for a paste of `size` bytes and marks it as used.
+    pub fn redeem(&self, challenge: &str, solution: &str, size: usize) -> Result<Redemption, PowError> {
+        let claims = self.verify_signature(challenge)?;
+        let now = unix_now();
+        if claims.expires_at <= now {
+            return Err(PowError::Expired);
+        }
+        if size as u64 > claims.max_size {
+            return Err(PowError::TooSmall {
+                max_size: claims.max_size,
+            });
+        }
+        if solution.len() > MAX_SOLUTION_LENGTH || !meets_difficulty(challenge, solution, claims.difficulty) {
+            return Err(PowError::Unsolved);
+        }
+        {
+            let mut spent = self.spent.lock().expect("proof-of-work lock poisoned");
+            if spent.contains_key(&claims.salt) {
+                return Err(PowError::Spent);
+            }
+            if spent.len() >= MAX_SPENT_CHALLENGES {
+                spent.retain(|_, expires_at| *expires_at > now);
+                if spent.len() >= MAX_SPENT_CHALLENGES {
+                    return Err(PowError::Busy);
+                }
+            }
+            spent.insert(claims.salt, claims.expires_at);
+        }
+        self.record_create();
+        Ok(Redemption { salt: claims.salt })
+    }
+
+    /// Forgets a redemption, so the same solution can pay for another attempt.
+    /// The create it was counted as stays in the load estimate.
+    pub fn refund(&self, redemption: Redemption) {
+        self.spent.lock().expect("proof-of-work lock poisoned").remove(&redemption.salt);
+    }
+
+    /// Forgets redeemed challenges that have expired and can no longer be replayed.
+    pub fn remove_expired(&self, now: u64) -> usize {
+        let mut spent = self.spent.lock().expect("proof-of-work lock poisoned");
+        let before = spent.len();
+        spent.retain(|_, expires_at| *expires_at > now);
+        before - spent.len()
+    }
+
+    /// Issues a challenge for `size` bytes and solves it, for tests of the
+    /// endpoints that take a proof.
+    #[cfg(test)]
+    pub fn issue_solved(&self, size: usize) -> (String, String) {
+        let challenge = self.issue(size).challenge.expect("challenges are always issued");
+        let difficulty = self.verify_signature(&challenge).expect("freshly issued").difficulty;
+        let solution = (0u64..)
+            .map(|n| n.to_string())
+            .find(|solution| meets_difficulty(&challenge, solution, difficulty))
+            .expect("some solution exists");
+        (challenge, solution)
+    }
+
+    fn verify_signature(&self, challenge: &str) -> Result<Claims, PowError> {
+        let bytes = base64_url.decode(challenge).map_err(|_| PowError::Malformed)?;
+        if bytes.len() != SIGNED_LENGTH + MAC_LENGTH || bytes[0] != CHALLENGE_VERSION {
+            return Err(PowError::Malformed);
+        }
+        let (signed, mac) = bytes.split_at(SIGNED_LENGTH);
+        self.mac(signed).verify_slice(mac).map_err(|_| PowError::BadSignature)?;
+        Ok(Claims {
+            expires_at: u64::from_be_bytes(signed[1..9].try_into().expect("fixed length")),
+            difficulty: signed[9],
+            max_size: u64::from_be_bytes(signed[10..18].try_into().expect("fixed length")),
+            salt: signed[18..].try_into().expect("fixed length"),
+        })
+    }
+
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac
+    }
+
+    fn creates_per_minute(&self) -> f64 {
+        let mut load = self.load.lock().expect("proof-of-work lock poisoned");
+        load.roll();
+        let elapsed = load.window_start.elapsed().as_secs_f64() / 60.0;
+        f64::from(load.previous) * (1.0 - elapsed) + f64::from(load.current)
+    }
+
+    fn record_create(&self) {
+        let mut load = self.load.lock().expect("proof-of-work lock poisoned");
+        load.roll();
+        load.current = load.current.saturating_add(1);
+    }
+}
+
+impl LoadMeter {
+    fn roll(&mut self) {
+        let minutes = self.window_start.elapsed().as_secs() / 60;
+        if minutes == 0 {
+            return;
+        }
+        self.previous = if minutes == 1 { self.current } else { 0 };
+        self.current = 0;
+        self.window_start += Duration::from_secs(minutes * 60);
+    }
+}
+
+fn meets_difficulty(challenge: &str, solution: &str, difficulty: u8) -> bool {
+    let hash = Sha256::new()
+        .chain_update(challenge)
+        .chain_update(b":")
+        .chain_update(solution)
+        .finalize();
+    let mut remaining = u32::from(difficulty);
+    for byte in hash {
+        if remaining == 0 {
+            break;
+        }
+        let needed = remaining.min(8);
+        if byte.leading_zeros() < needed {
+            return false;
+        }
+        remaining -= needed;
+    }
+    remaining == 0
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeQuery {
+    /// Size in bytes of the paste about to be created; its request body size is a safe upper bound.
+    #[serde(default)]
+    size: usize,
+}
+
+#[derive(Serialize)]
+pub struct ChallengeResponse {
+    /// False when the server does not ask for proof of work; the other fields are then absent.
+    required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    challenge: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    difficulty: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_in_secs: Option<u64>,
+}
+
+// --- Route Handlers ---
+pub async fn handle_challenge(
+    State(state): State<SharedState>,
+    Query(query): Query<ChallengeQuery>,
+) -> Result<Json<ChallengeResponse>, (StatusCode, String)> {
+    let Some(pow) = &state.proof_of_work else {
+        return Ok(Json(ChallengeResponse {
+            required: false,
+            challenge: None,
+            difficulty: None,
+            max_size: None,
+            expires_in_secs: None,
+        }));
+    };
+    let max_size = state.config.max_upload_bytes.max(json_body_limit(state.config.max_paste_bytes));
+    if query.size > max_size {
+        return Err((StatusCode::BAD_REQUEST, format!("Size exceeds the {} byte upload limit", max_size)));
+    }
+    Ok(Json(pow.issue(query.size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFFICULTY: u8 = 8;
+
+    fn pow() -> ProofOfWork {
+        let config = PowConfig {
+            base_difficulty: DIFFICULTY,
+            max_difficulty: DIFFICULTY,
+            load_threshold: 0,
+        };
+        ProofOfWork::new(config, [7; KEY_LENGTH])
+    }
+
+    fn challenge(pow: &ProofOfWork, size: usize) -> String {
+        pow.issue(size).challenge.expect("proof of work is enabled")
+    }
+
+    fn leading_zero_bits(challenge: &str, solution: &str) -> u32 {
+        let hash = Sha256::new().chain_update(challenge).chain_update(b":").chain_update(solution).finalize();
+        let zero_bytes = hash.iter().take_while(|byte| **byte == 0).count();
+        zero_bytes as u32 * 8 + hash.get(zero_bytes).map_or(0, |byte| byte.leading_zeros())
+    }
+
+    /// First solution whose hash starts with exactly `bits` zero bits.
+    fn solve_exactly(challenge: &str, bits: u32) -> String {
+        (0u64..)
+            .map(|n| n.to_string())
+            .find(|solution| leading_zero_bits(challenge, solution) == bits)
+            .unwrap()
+    }
+
+    /// Signs a challenge with arbitrary fields, as `issue` would.
+    fn forge(pow: &ProofOfWork, version: u8, expires_at: u64, difficulty: u8) -> String {
+        let mut challenge = vec![version];
+        challenge.extend_from_slice(&expires_at.to_be_bytes());
+        challenge.push(difficulty);
+        challenge.extend_from_slice(&1024u64.to_be_bytes());
+        challenge.extend_from_slice(&[1; SALT_LENGTH]);
+        let mac = pow.mac(&challenge).finalize().into_bytes();
+        challenge.extend_from_slice(&mac);
+        base64_url.encode(challenge)
+    }
+
+    #[test]
+    fn meets_difficulty_counts_bits_across_bytes() {
+        let challenge = "test";
+        let solution = solve_exactly(challenge, 9);
+        assert!(meets_difficulty(challenge, &solution, 0));
+        assert!(meets_difficulty(challenge, &solution, 9));
+        assert!(!meets_difficulty(challenge, &solution, 10));
+    }
+
+    #[test]
+    fn solution_one_bit_short_is_rejected() {
+        let pow = pow();
+        let challenge = challenge(&pow, 100);
+        let short = solve_exactly(&challenge, u32::from(DIFFICULTY) - 1);
+        assert_eq!(pow.redeem(&challenge, &short, 100).err(), Some(PowError::Unsolved));
+        let solved = solve_exactly(&challenge, u32::from(DIFFICULTY));
+        assert!(pow.redeem(&challenge, &solved, 100).is_ok());
+    }
+
+    #[test]
+    fn replayed_challenge_is_rejected() {
+        let pow = pow();
+        let challenge = challenge(&pow, 100);
+        let solved = solve_exactly(&challenge, u32::from(DIFFICULTY));
+        assert!(pow.redeem(&challenge, &solved, 100).is_ok());
+        assert_eq!(pow.redeem(&challenge, &solved, 100).err(), Some(PowError::Spent));
+        let other = solve_exactly(&challenge, u32::from(DIFFICULTY) + 1);
+        assert_eq!(pow.redeem(&challenge, &other, 100).err(), Some(PowError::Spent));
+    }
+
+    #[test]
+    fn challenge_covers_only_its_size() {
+        let pow = pow();
+        let challenge = challenge(&pow, 100);
+        let solved = solve_exactly(&challenge, u32::from(DIFFICULTY));
+        assert_eq!(pow.redeem(&challenge, &solved, 101).err(), Some(PowError::TooSmall { max_size: 100 }));
+    }
+
+    #[test]
+    fn tampered_or_malformed_challenges_are_rejected() {
+        let pow = pow();
+        let mut bytes = base64_url.decode(challenge(&pow, 100)).unwrap();
+        bytes[9] = 0; // Lower the difficulty
+        assert_eq!(pow.redeem(&base64_url.encode(&bytes), "x", 100).err(), Some(PowError::BadSignature));
+        assert_eq!(pow.redeem(&base64_url.encode(&bytes[1..]), "x", 100).err(), Some(PowError::Malformed));
+        assert_eq!(pow.redeem("not base64!", "x", 100).err(), Some(PowError::Malformed));
+
+        let other_key = ProofOfWork::new(pow.config, [8; KEY_LENGTH]);
+        assert_eq!(pow.redeem(&challenge(&other_key, 100), "x", 100).err(), Some(PowError::BadSignature));
+
+        let wrong_version = forge(&pow, CHALLENGE_VERSION + 1, unix_now() + 60, 0);
+        assert_eq!(pow.redeem(&wrong_version, "x", 100).err(), Some(PowError::Malformed));
+        let expired = forge(&pow, CHALLENGE_VERSION, unix_now() - 1, 0);
+        assert_eq!(pow.redeem(&expired, "x", 100).err(), Some(PowError::Expired));
+    }
+
+    #[test]
+    fn spent_challenges_are_capped() {
+        let pow = pow();
+        let expires_at = unix_now() + 60;
+        {
+            let mut spent = pow.spent.lock().unwrap();
+            for n in 0..MAX_SPENT_CHALLENGES as u128 {
+                spent.insert(n.to_be_bytes(), expires_at);
+            }
+        }
+        let challenge = challenge(&pow, 100);
+        let solved = solve_exactly(&challenge, u32::from(DIFFICULTY));
+        assert_eq!(pow.redeem(&challenge, &solved, 100).err(), Some(PowError::Busy));
+
+        assert_eq!(pow.remove_expired(expires_at), MAX_SPENT_CHALLENGES);
+        assert!(pow.redeem(&challenge, &solved, 100).is_ok());
+    }
+
+    #[test]
+    fn difficulty_rises_with_size() {
+        let config = PowConfig {
+            base_difficulty: 10,
+            max_difficulty: 13,
+            load_threshold: 0,
+        };
+        let pow = ProofOfWork::new(config, [7; KEY_LENGTH]);
+        assert_eq!(pow.difficulty(0), 10);
+        assert_eq!(pow.difficulty(SIZE_STEP), 10);
+        assert_eq!(pow.difficulty(SIZE_STEP + 1), 11);
+        assert_eq!(pow.difficulty(4 * SIZE_STEP), 12);
+        assert_eq!(pow.difficulty(1024 * SIZE_STEP), 13);
+    }
+
+    #[test]
+    fn refunded_challenge_can_be_redeemed_again() {
+        let pow = pow();
+        let challenge = challenge(&pow, 100);
+        let solved = solve_exactly(&challenge, u32::from(DIFFICULTY));
+        pow.refund(pow.redeem(&challenge, &solved, 100).unwrap());
+        let redemption = pow.redeem(&challenge, &solved, 100).unwrap();
+        assert_eq!(pow.redeem(&challenge, &solved, 100).err(), Some(PowError::Spent));
+        drop(redemption);
+        assert_eq!(pow.redeem(&challenge, &solved, 100).err(), Some(PowError::Spent));
+    }
+}
@@ -0,0 +1,557 @@
+use crate::SharedState;
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::HeaderName, request::Parts, HeaderMap, StatusCode},
+    middleware::AddExtension,
+    Extension,
+};
+use axum_server::accept::Accept;
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+use tower_layer::Layer;
+use tracing::warn;
+
+// --- Configuration Constants ---
+const FORWARDED_HEADER: HeaderName = HeaderName::from_static("forwarded");
+const X_FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5); // Time a proxy gets to send the PROXY header
+const PROXY_V1_PREFIX: &[u8] = b"PROXY ";
+const PROXY_V1_MAX_LENGTH: usize = 107; // Longest valid v1 line, including CRLF
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const PROXY_V2_MAX_PAYLOAD: usize = 4096; // Addresses plus TLVs; real proxies send far less
+
+// --- Data Structures ---
+/// An IP address range such as `10.0.0.0/8` or `fd00::/8`. A bare address
+/// is a range of one.
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(net.to_bits(), ip.to_bits(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_matches(net.to_bits(), ip.to_bits(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches<T>(net: T, ip: T, prefix: u8) -> bool
+where
+    T: Copy + Eq + std::ops::BitXor<Output = T> + std::ops::Shr<u32, Output = T> + From<u8>,
+{
+    let bits = 8 * std::mem::size_of::<T>() as u32;
+    let prefix = u32::from(prefix);
+    // Compare only the top `prefix` bits; a zero prefix matches everything.
+    prefix == 0 || (net ^ ip) >> (bits - prefix) == T::from(0)
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("invalid IP address in {:?}", s))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max_prefix,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Reverse proxies whose forwarding headers are believed.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    pub fn new(ranges: Vec<Cidr>) -> Self {
+        Self { ranges }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    /// Finds the client behind any trusted proxies. `peer` is the address the
+    /// connection came from (or the PROXY protocol source). Forwarding headers
+    /// are walked from the nearest hop outwards and only while each hop is a
+    /// trusted proxy, so a client cannot spoof its address by sending them.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+        let chain = if headers.contains_key(FORWARDED_HEADER) {
+            forwarded_chain(headers)
+        } else {
+            x_forwarded_for_chain(headers)
+        };
+        for hop in chain.into_iter().rev() {
+            // An unparsable hop ends the walk at the last proxy we could vouch for.
+            let Some(hop) = hop.map(|hop| hop.to_canonical()) else { break };
+            client = hop;
+            if !self.contains(hop) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Source address announced by a PROXY protocol header, attached to every
+/// request on that connection. `None` when the proxy sent `LOCAL`/`UNKNOWN`
+/// or PROXY protocol is off.
+#[derive(Clone, Copy, Debug)]
+pub struct ProxiedPeer(pub Option<SocketAddr>);
+
+/// The real client address, after PROXY protocol and trusted forwarding
+/// headers have been taken into account.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<SharedState> for ClientIp {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &SharedState) -> Result<Self, Self::Rejection> {
+        let proxied = parts.extensions.get::<ProxiedPeer>().and_then(|peer| peer.0);
+        let Some(peer) = proxied.or_else(|| parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0)) else {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Client address unavailable".to_string()));
+        };
+        Ok(Self(state.config.trusted_proxies.resolve(peer.ip(), &parts.headers)))
+    }
+}
+
+// --- Forwarding Headers ---
+/// Hops of RFC 7239 `Forwarded` headers, client first. `None` marks a hop
+/// without a usable `for=` address (e.g. `unknown` or an obfuscated name).
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(FORWARDED_HEADER)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+/// Hops of `X-Forwarded-For` headers, client first.
+fn x_forwarded_for_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(X_FORWARDED_FOR_HEADER)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+/// Parses `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`, `[2001:db8::1]` or `[2001:db8::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+// --- PROXY Protocol ---
+/// Listener hook that reads a PROXY protocol v1 or v2 header from each new
+/// connection before TLS or HTTP start. When enabled, every connection must
+/// come from a trusted proxy and begin with a header; others are dropped.
+#[derive(Clone)]
+pub struct ProxyProtocolAcceptor {
+    enabled: bool,
+    trusted: TrustedProxies,
+}
+
+impl ProxyProtocolAcceptor {
+    pub fn new(enabled: bool, trusted: TrustedProxies) -> Self {
+        Self { enabled, trusted }
+    }
+}
+
+impl<S> Accept<TcpStream, S> for ProxyProtocolAcceptor
+where
+    S: Send + 'static,
+{
+    type Stream = TcpStream;
+    type Service = AddExtension<S, ProxiedPeer>;
+    type Future = std::pin::Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let acceptor = self.clone();
+        Box::pin(async move {
+            if !acceptor.enabled {
+                return Ok((stream, Extension(ProxiedPeer(None)).layer(service)));
+            }
+            let peer = stream.peer_addr()?;
+            if !acceptor.trusted.contains(peer.ip()) {
+                warn!("Dropped connection from {}: not a trusted proxy", peer.ip());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "untrusted PROXY protocol peer"));
+            }
+            let source = tokio::time::timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut stream))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY protocol header timed out"))
+                .and_then(|result| result)
+                .inspect_err(|e| warn!("Dropped connection from {}: {}", peer.ip(), e))?;
+            Ok((stream, Extension(ProxiedPeer(source)).layer(service)))
+        })
+    }
+}
+
+fn invalid_header(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid PROXY protocol header: {}", message))
+}
+
+/// Consumes exactly the PROXY header, leaving the stream at the first byte of
+/// the proxied connection.
+async fn read_proxy_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0u8; 16];
+    stream.read_exact(&mut start[..PROXY_V1_PREFIX.len()]).await?;
+    if &start[..PROXY_V1_PREFIX.len()] == PROXY_V1_PREFIX {
+        return read_proxy_v1(stream).await;
+    }
+    stream.read_exact(&mut start[PROXY_V1_PREFIX.len()..]).await?;
+    if &start[..12] != PROXY_V2_SIGNATURE {
+        return Err(invalid_header("missing signature"));
+    }
+    read_proxy_v2(stream, start).await
+}
+
+/// Text format: `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n` (the
+/// `PROXY ` prefix has already been read).
+async fn read_proxy_v1<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(PROXY_V1_MAX_LENGTH);
+    while !line.ends_with(b"\r\n") {
+        if line.len() + PROXY_V1_PREFIX.len() >= PROXY_V1_MAX_LENGTH {
+            return Err(invalid_header("v1 line too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| invalid_header("v1 line is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["UNKNOWN", ..] => Ok(None),
+        [protocol @ ("TCP4" | "TCP6"), source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid_header("bad v1 source address"))?;
+            let port: u16 = source_port.parse().map_err(|_| invalid_header("bad v1 source port"))?;
+            if ip.is_ipv4() != (*protocol == "TCP4") {
+                return Err(invalid_header("v1 address does not match protocol"));
+            }
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid_header("malformed v1 line")),
+    }
+}
+
+/// Binary format: signature, version/command, family/protocol, payload
+/// length, then addresses and optional TLVs, which are skipped.
+async fn read_proxy_v2<R: AsyncRead + Unpin>(stream: &mut R, start: [u8; 16]) -> io::Result<Option<SocketAddr>> {
+    let (version_command, family) = (start[12], start[13]);
+    let length = usize::from(u16::from_be_bytes([start[14], start[15]]));
+    if version_command >> 4 != 2 {
+        return Err(invalid_header("unsupported v2 version"));
+    }
+    if length > PROXY_V2_MAX_PAYLOAD {
+        return Err(invalid_header("v2 payload too long"));
+    }
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+    match version_command & 0x0f {
+        0x0 => return Ok(None), // LOCAL: the proxy's own health check
+        0x1 => {}
+        _ => return Err(invalid_header("unsupported v2 command")),
+    }
+    match family >> 4 {
+        0x1 => {
+            let addresses: [u8; 12] = payload
+                .get(..12)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| invalid_header("short v2 IPv4 addresses"))?;
+            let ip = Ipv4Addr::from([addresses[0], addresses[1], addresses[2], addresses[3]]);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addresses[8], addresses[9]]))))
+        }
+        0x2 => {
+            let addresses: [u8; 36] = payload
+                .get(..36)
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| invalid_header("short v2 IPv6 addresses"))?;
+            let octets: [u8; 16] = addresses[..16].try_into().expect("slice is 16 bytes");
+            let ip = Ipv6Addr::from(octets);
+            Ok(Some(SocketAddr::new(ip.into(), u16::from_be_bytes([addresses[32], addresses[33]]))))
+        }
+        // AF_UNSPEC and AF_UNIX carry no usable client IP.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn trusted(ranges: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(ranges.iter().map(|range| range.parse().unwrap()).collect())
+    }
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn headers_with_xff(value: &str) -> HeaderMap {
+        headers(&[(X_FORWARDED_FOR_HEADER, value)])
+    }
+
+    fn headers_forwarded(value: &str) -> HeaderMap {
+        headers(&[(FORWARDED_HEADER, value)])
+    }
+
+    /// Reads a PROXY header from `bytes` and returns what is left after it.
+    async fn read_header(bytes: &[u8]) -> (io::Result<Option<SocketAddr>>, Vec<u8>) {
+        let mut stream = bytes;
+        let result = read_proxy_header(&mut stream).await;
+        (result, stream.to_vec())
+    }
+
+    fn v2_header(version_command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = PROXY_V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[version_command, family]);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    fn ip_octets(s: &str) -> Vec<u8> {
+        s.parse::<Ipv6Addr>().unwrap().octets().to_vec()
+    }
+
+    fn assert_invalid(result: io::Result<Option<SocketAddr>>, message: &str) {
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains(message), "{}", e);
+    }
+
+    #[test]
+    fn cidr_parsing_and_matching() {
+        let v4: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(v4.contains(ip("10.255.0.1")));
+        assert!(!v4.contains(ip("11.0.0.1")));
+        assert!(v4.contains(ip("::ffff:10.1.2.3")), "IPv4-mapped addresses match IPv4 ranges");
+        assert!(!v4.contains(ip("fd00::1")));
+
+        let v6: Cidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+
+        let single: Cidr = "192.168.1.1".parse().unwrap();
+        assert!(single.contains(ip("192.168.1.1")));
+        assert!(!single.contains(ip("192.168.1.2")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fd00::/129".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("proxy.local".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn parse_node_forms() {
+        assert_eq!(parse_node("1.2.3.4"), Some(ip("1.2.3.4")));
+        assert_eq!(parse_node("1.2.3.4:80"), Some(ip("1.2.3.4")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[2001:db8::1]:80"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[1.2.3.4]"), None);
+        assert_eq!(parse_node("[2001:db8::1"), None);
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node(""), None);
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarding_headers() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[(X_FORWARDED_FOR_HEADER, "203.0.113.7")]);
+        assert_eq!(proxies.resolve(ip("198.51.100.1"), &headers), ip("198.51.100.1"));
+        assert_eq!(TrustedProxies::default().resolve(ip("10.0.0.1"), &headers), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn spoofed_leftmost_x_forwarded_for_is_ignored() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        // The client sent "X-Forwarded-For: 6.6.6.6"; the proxy appended the address it saw.
+        let headers = headers(&[(X_FORWARDED_FOR_HEADER, "6.6.6.6, 203.0.113.7")]);
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn x_forwarded_for_walks_trusted_hops() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[
+            (X_FORWARDED_FOR_HEADER, "6.6.6.6, 203.0.113.7"),
+            (X_FORWARDED_FOR_HEADER, "10.0.0.2"),
+        ]);
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+
+        let all_trusted = headers_with_xff("10.0.0.3, 10.0.0.2");
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &all_trusted), ip("10.0.0.3"));
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn unparsable_hop_stops_at_last_trusted_proxy() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers_with_xff("203.0.113.7, garbage")), ip("10.0.0.1"));
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &headers_with_xff("203.0.113.7, garbage, 10.0.0.2")),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn forwarded_header_takes_precedence() {
+        let proxies = trusted(&["10.0.0.0/8"]);
+        let headers = headers(&[
+            (FORWARDED_HEADER, "for=6.6.6.6, for=\"[2001:db8::1]:443\";proto=https"),
+            (X_FORWARDED_FOR_HEADER, "203.0.113.7"),
+        ]);
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("2001:db8::1"));
+
+        let obfuscated = headers_forwarded("for=203.0.113.7, for=_hidden");
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &obfuscated), ip("10.0.0.1"));
+        let by_only = headers_forwarded("by=10.0.0.1;proto=http");
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &by_only), ip("10.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn proxy_v1_headers() {
+        let (result, rest) = read_header(b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443\r\nGET /").await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let (result, _) = read_header(b"PROXY TCP6 2001:db8::1 ::1 56324 443\r\n").await;
+        assert_eq!(result.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+        let (result, rest) = read_header(b"PROXY UNKNOWN whatever\r\nGET /").await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn malformed_proxy_v1_headers() {
+        let (result, _) = read_header(b"PROXY TCP4 2001:db8::1 ::1 56324 443\r\n").await;
+        assert_invalid(result, "does not match protocol");
+        let (result, _) = read_header(b"PROXY TCP4 203.0.113.7 10.0.0.1 70000 443\r\n").await;
+        assert_invalid(result, "bad v1 source port");
+        let (result, _) = read_header(b"PROXY TCP4 203.0.113.7 10.0.0.1 56324\r\n").await;
+        assert_invalid(result, "malformed v1 line");
+        let (result, _) = read_header(b"PROXY TCP4 203.0.113.7\n").await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let long = [b"PROXY UNKNOWN ".as_slice(), &[b'x'; PROXY_V1_MAX_LENGTH], b"\r\n"].concat();
+        let (result, _) = read_header(&long).await;
+        assert_invalid(result, "v1 line too long");
+        let (result, _) = read_header(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert_invalid(result, "missing signature");
+    }
+
+    #[tokio::test]
+    async fn proxy_v2_headers() {
+        let mut ipv4 = vec![203, 0, 113, 7, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb];
+        ipv4.extend_from_slice(&[0x04, 0x00, 0x01, 0xff]); // A TLV, skipped
+        let header = [v2_header(0x21, 0x11, &ipv4), b"GET /".to_vec()].concat();
+        let (result, rest) = read_header(&header).await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET /");
+
+        let mut ipv6 = ip_octets("2001:db8::1");
+        ipv6.extend(ip_octets("::1"));
+        ipv6.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        let (result, _) = read_header(&v2_header(0x21, 0x21, &ipv6)).await;
+        assert_eq!(result.unwrap(), Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        let (result, rest) = read_header(&[v2_header(0x20, 0x00, &[]), b"GET /".to_vec()].concat()).await;
+        assert_eq!(result.unwrap(), None, "LOCAL carries no client address");
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn proxy_v2_unsupported_family_has_no_address() {
+        // AF_UNIX stream: 108-byte source and destination paths
+        let (result, rest) = read_header(&[v2_header(0x21, 0x31, &[0; 216]), b"GET /".to_vec()].concat()).await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET /");
+        let (result, _) = read_header(&v2_header(0x21, 0x00, &[])).await;
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn truncated_proxy_v2_headers() {
+        let full = v2_header(0x21, 0x11, &[203, 0, 113, 7, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        for length in [8, 12, 15, full.len() - 1] {
+            let (result, _) = read_header(&full[..length]).await;
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof, "cut at {}", length);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_proxy_v2_headers() {
+        let (result, _) = read_header(&v2_header(0x21, 0x11, &[203, 0, 113, 7])).await;
+        assert_invalid(result, "short v2 IPv4 addresses");
+        let (result, _) = read_header(&v2_header(0x21, 0x21, &[0; 12])).await;
+        assert_invalid(result, "short v2 IPv6 addresses");
+        let (result, _) = read_header(&v2_header(0x11, 0x11, &[0; 12])).await;
+        assert_invalid(result, "unsupported v2 version");
+        let (result, _) = read_header(&v2_header(0x22, 0x11, &[0; 12])).await;
+        assert_invalid(result, "unsupported v2 command");
+        let (result, _) = read_header(&v2_header(0x21, 0x11, &[0; PROXY_V2_MAX_PAYLOAD + 1])).await;
+        assert_invalid(result, "v2 payload too long");
+
+        let mut bad_signature = v2_header(0x21, 0x11, &[0; 12]);
+        bad_signature[11] = b'X';
+        let (result, _) = read_header(&bad_signature).await;
+        assert_invalid(result, "missing signature");
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{proxy::ClientIp, SharedState};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// --- Configuration Constants ---
+pub const DEFAULT_CREATE_PER_MINUTE: u32 = 10;
+pub const DEFAULT_CREATE_BURST: u32 = 20;
+pub const DEFAULT_READ_PER_MINUTE: u32 = 120;
+pub const DEFAULT_READ_BURST: u32 = 60;
+pub const DEFAULT_FAILED_LOOKUP_PER_MINUTE: u32 = 10;
+pub const DEFAULT_FAILED_LOOKUP_BURST: u32 = 20;
+
+// --- Data Structures ---
+/// Which budget a request draws from. Each client has one bucket per budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Budget {
+    /// New pastes and upload sessions.
+    Create,
+    /// Paste reads, reveals and deletions.
+    Read,
+    /// Reads that hit no paste. Drained after the fact, so an empty bucket
+    /// blocks all reads from an IP that is guessing IDs.
+    FailedLookup,
+}
+
+/// Refill rate and burst size of one budget. A rate of zero disables it.
+#[derive(Clone, Copy, Debug)]
+pub struct BucketLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimits {
+    pub create: BucketLimit,
+    pub read: BucketLimit,
+    pub failed_lookup: BucketLimit,
+}
+
+impl RateLimits {
+    fn get(&self, budget: Budget) -> BucketLimit {
+        match budget {
+            Budget::Create => self.create,
+            Budget::Read => self.read,
+            Budget::FailedLookup => self.failed_lookup,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-client token buckets, kept in memory only. A client is an IPv4
+/// address or an IPv6 /64, see [`client_key`].
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(IpAddr, Budget), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes one token, or returns how long until one is available.
+    pub fn acquire(&self, ip: IpAddr, budget: Budget) -> Result<(), Duration> {
+        self.update(ip, budget, true)
+    }
+
+    /// Like [`RateLimiter::acquire`] but leaves the bucket as it is.
+    pub fn check(&self, ip: IpAddr, budget: Budget) -> Result<(), Duration> {
+        self.update(ip, budget, false)
+    }
+
+    fn update(&self, ip: IpAddr, budget: Budget, consume: bool) -> Result<(), Duration> {
+        let limit = self.limits.get(budget);
+        if limit.per_minute == 0 {
+            return Ok(());
+        }
+        let rate = f64::from(limit.per_minute) / 60.0;
+        let burst = f64::from(limit.burst.max(1));
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let bucket = buckets.entry((client_key(ip), budget)).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate));
+        }
+        if consume {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Drops buckets that have refilled completely; they behave exactly like
+    /// a fresh bucket. Returns how many were removed.
+    pub fn remove_stale(&self) -> usize {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let before = buckets.len();
+        buckets.retain(|(_, budget), bucket| {
+            let limit = self.limits.get(*budget);
+            let refill_secs = f64::from(limit.burst.max(1)) * 60.0 / f64::from(limit.per_minute.max(1));
+            now.duration_since(bucket.updated).as_secs_f64() < refill_secs
+        });
+        before - buckets.len()
+    }
+}
+
+/// Address that stands for the client in bucket keys. An IPv6 client is
+/// normally handed at least a /64 and could rotate through it for fresh
+/// buckets, so the whole /64 shares one set. IPv4-mapped IPv6 addresses count
+/// as the IPv4 address they carry.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & (u128::MAX << 64))),
+        },
+    }
+}
+
+// --- Middleware ---
+/// Charges the create budget. Applied to endpoints that store new data.
+pub async fn limit_create(
+    State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(retry_after) = state.rate_limiter.acquire(ip, Budget::Create) {
+        warn!("Rate limited create request from {}", ip);
+        return too_many_requests(retry_after);
+    }
+    next.run(request).await
+}
+
+/// Charges the read budget, and the failed-lookup budget whenever the paste
+/// does not exist.
+pub async fn limit_read(
+    State(state): State<SharedState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = &state.rate_limiter;
+    if let Err(retry_after) = limiter.check(ip, Budget::FailedLookup).and_then(|()| limiter.acquire(ip, Budget::Read)) {
+        warn!("Rate limited read request from {}", ip);
+        return too_many_requests(retry_after);
+    }
+    let response = next.run(request).await;
+    if response.status() == StatusCode::NOT_FOUND {
+        // An empty bucket is reported on the next request, not this one.
+        let _ = limiter.acquire(ip, Budget::FailedLookup);
+    }
+    response
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        format!("Too many requests. Try again in {} seconds.", secs),
+    )
+        .into_response();
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 7));
+    const OTHER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 8));
+
+    fn limiter(per_minute: u32, burst: u32) -> RateLimiter {
+        let limit = BucketLimit { per_minute, burst };
+        RateLimiter::new(RateLimits {
+            create: limit,
+            read: limit,
+            failed_lookup: limit,
+        })
+    }
+
+    /// Pretends `elapsed` has passed since the bucket was last updated.
+    fn age(limiter: &RateLimiter, ip: IpAddr, budget: Budget, elapsed: Duration) {
+        let mut buckets = limiter.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(&(client_key(ip), budget)).unwrap();
+        bucket.updated -= elapsed;
+    }
+
+    #[test]
+    fn burst_then_refill_rate() {
+        let limiter = limiter(60, 3);
+        for _ in 0..3 {
+            assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        }
+        let retry_after = limiter.acquire(CLIENT, Budget::Create).unwrap_err();
+        assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1), "{:?}", retry_after);
+
+        age(&limiter, CLIENT, Budget::Create, Duration::from_millis(1500));
+        assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        assert!(limiter.acquire(CLIENT, Budget::Create).is_err());
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let limiter = limiter(60, 2);
+        assert_eq!(limiter.acquire(CLIENT, Budget::Read), Ok(()));
+        age(&limiter, CLIENT, Budget::Read, Duration::from_secs(10));
+        assert_eq!(limiter.acquire(CLIENT, Budget::Read), Ok(()));
+        assert_eq!(limiter.acquire(CLIENT, Budget::Read), Ok(()));
+        assert!(limiter.acquire(CLIENT, Budget::Read).is_err());
+    }
+
+    #[test]
+    fn check_does_not_consume() {
+        let limiter = limiter(60, 1);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(CLIENT, Budget::FailedLookup), Ok(()));
+        }
+        assert_eq!(limiter.acquire(CLIENT, Budget::FailedLookup), Ok(()));
+        assert!(limiter.check(CLIENT, Budget::FailedLookup).is_err());
+    }
+
+    #[test]
+    fn buckets_are_per_client_and_budget() {
+        let limiter = limiter(60, 1);
+        assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        assert!(limiter.acquire(CLIENT, Budget::Create).is_err());
+        assert_eq!(limiter.acquire(CLIENT, Budget::Read), Ok(()));
+        assert_eq!(limiter.acquire(OTHER, Budget::Create), Ok(()));
+    }
+
+    #[test]
+    fn ipv6_clients_share_buckets_per_64() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let limiter = limiter(60, 1);
+        assert_eq!(limiter.acquire(ip("2001:db8:1:2::1"), Budget::FailedLookup), Ok(()));
+        assert!(limiter.acquire(ip("2001:db8:1:2:ffff:ffff:ffff:ffff"), Budget::FailedLookup).is_err());
+        assert!(limiter.check(ip("2001:db8:1:2:abcd::99"), Budget::FailedLookup).is_err());
+        assert_eq!(limiter.acquire(ip("2001:db8:1:3::1"), Budget::FailedLookup), Ok(()));
+
+        assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        assert!(limiter.acquire(ip("::ffff:203.0.113.7"), Budget::Create).is_err(), "IPv4-mapped is the same client");
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn zero_rate_disables_budget() {
+        let limiter = limiter(0, 0);
+        for _ in 0..100 {
+            assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        }
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn zero_burst_still_allows_one_request() {
+        let limiter = limiter(60, 0);
+        assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        assert!(limiter.acquire(CLIENT, Budget::Create).is_err());
+    }
+
+    #[test]
+    fn stale_buckets_are_removed_once_refilled() {
+        let limiter = limiter(60, 5);
+        assert_eq!(limiter.acquire(CLIENT, Budget::Create), Ok(()));
+        assert_eq!(limiter.acquire(OTHER, Budget::Create), Ok(()));
+        assert_eq!(limiter.remove_stale(), 0);
+
+        age(&limiter, CLIENT, Budget::Create, Duration::from_secs(5));
+        assert_eq!(limiter.remove_stale(), 1);
+        assert!(!limiter.buckets.lock().unwrap().contains_key(&(CLIENT, Budget::Create)));
+    }
+}
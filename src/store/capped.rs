@@ -0,0 +1,228 @@
+use super::{
+    AccessFailure, AccessGuard, AccessLimits, EncryptedBlob, EncryptedPaste, EvictionOrder, PasteStore, StoreError,
+    StoreStats,
+};
+use crate::logging;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use tokio::sync::Mutex;
+use tracing::{info, instrument, warn};
+
+/// What to do when a new paste would exceed the capacity limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CapacityPolicy {
+    /// Refuse the new paste (default).
+    Reject,
+    /// Evict the oldest pastes until the new one fits.
+    EvictOldest,
+    /// Evict the least recently read pastes until the new one fits.
+    EvictLru,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CapacityLimits {
+    pub max_total_bytes: usize,
+    pub max_pastes: usize,
+    pub policy: CapacityPolicy,
+}
+
+/// Wraps any backend with global limits on stored bytes and paste count.
+/// Every store call goes through here, so this is also where storage
+/// operations get their trace spans, which carry no paste IDs.
+///
+/// Inserts are admitted one at a time so concurrent writers cannot overshoot
+/// the limits between the usage check and the insert.
+pub struct CappedStore {
+    inner: Box<dyn PasteStore>,
+    limits: CapacityLimits,
+    admission: Mutex<()>,
+}
+
+impl CappedStore {
+    pub fn new(inner: Box<dyn PasteStore>, limits: CapacityLimits) -> Self {
+        Self {
+            inner,
+            limits,
+            admission: Mutex::new(()),
+        }
+    }
+
+    fn fits(&self, stats: &StoreStats, size: usize) -> bool {
+        stats.paste_count < self.limits.max_pastes
+            && stats.total_bytes.saturating_add(size) <= self.limits.max_total_bytes
+    }
+}
+
+#[async_trait]
+impl PasteStore for CappedStore {
+    #[instrument(name = "store.insert", skip_all)]
+    async fn insert(&self, id: String, paste: EncryptedPaste) -> Result<(), StoreError> {
+        let size = paste.size();
+        if size > self.limits.max_total_bytes || self.limits.max_pastes == 0 {
+            return Err(StoreError::Full);
+        }
+
+        let _admission = self.admission.lock().await;
+        loop {
+            let stats = self.inner.stats().await?;
+            if self.fits(&stats, size) {
+                break;
+            }
+            let order = match self.limits.policy {
+                CapacityPolicy::Reject => {
+                    warn!(
+                        "Rejecting paste: store at capacity ({} pastes, {} bytes)",
+                        stats.paste_count, stats.total_bytes
+                    );
+                    return Err(StoreError::Full);
+                }
+                CapacityPolicy::EvictOldest => EvictionOrder::Oldest,
+                CapacityPolicy::EvictLru => EvictionOrder::LeastRecentlyRead,
+            };
+            match self.inner.evict_one(order).await? {
+                Some((evicted_id, evicted_size)) => {
+                    info!("Evicted paste {} ({} bytes) to free capacity", logging::paste_id(&evicted_id), evicted_size)
+                }
+                None => return Err(StoreError::Full),
+            }
+        }
+        self.inner.insert(id, paste).await
+    }
+
+    #[instrument(name = "store.get", skip_all)]
+    async fn get(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        self.inner.get(id).await
+    }
+
+    #[instrument(name = "store.take_view", skip_all)]
+    async fn take_view(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        self.inner.take_view(id).await
+    }
+
+    #[instrument(name = "store.get_chunk", skip_all)]
+    async fn get_chunk(&self, id: &str, index: usize) -> Result<Option<EncryptedBlob>, StoreError> {
+        self.inner.get_chunk(id, index).await
+    }
+
+    #[instrument(name = "store.access_guard", skip_all)]
+    async fn access_guard(&self, id: &str) -> Result<Option<AccessGuard>, StoreError> {
+        self.inner.access_guard(id).await
+    }
+
+    #[instrument(name = "store.record_failed_access", skip_all)]
+    async fn record_failed_access(
+        &self,
+        id: &str,
+        now: u64,
+        limits: AccessLimits,
+    ) -> Result<Option<AccessFailure>, StoreError> {
+        self.inner.record_failed_access(id, now, limits).await
+    }
+
+    #[instrument(name = "store.delete", skip_all)]
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        self.inner.delete(id).await
+    }
+
+    #[instrument(name = "store.delete_expired", skip_all)]
+    async fn delete_expired(&self, now: u64) -> Result<usize, StoreError> {
+        self.inner.delete_expired(now).await
+    }
+
+    #[instrument(name = "store.stats", skip_all)]
+    async fn stats(&self) -> Result<StoreStats, StoreError> {
+        self.inner.stats().await
+    }
+
+    #[instrument(name = "store.evict_one", skip_all)]
+    async fn evict_one(&self, order: EvictionOrder) -> Result<Option<(String, usize)>, StoreError> {
+        self.inner.evict_one(order).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests as shared, unix_now, MemoryStore};
+    use super::*;
+
+    fn capped(max_pastes: usize, max_total_bytes: usize, policy: CapacityPolicy) -> CappedStore {
+        CappedStore::new(
+            Box::new(MemoryStore::new()),
+            CapacityLimits {
+                max_total_bytes,
+                max_pastes,
+                policy,
+            },
+        )
+    }
+
+    /// Fills the store with pastes created in order, then reads the oldest.
+    async fn fill(store: &CappedStore) {
+        for (id, created_at) in [("first", 100), ("second", 200), ("third", 300)] {
+            store.insert(id.to_string(), shared::paste(created_at, None)).await.unwrap();
+        }
+        store.get("first").await.unwrap();
+    }
+
+    async fn ids(store: &CappedStore) -> Vec<&'static str> {
+        let mut ids = Vec::new();
+        for id in ["first", "second", "third", "new"] {
+            if store.get(id).await.unwrap().is_some() {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn reject_refuses_when_full() {
+        let store = capped(3, usize::MAX, CapacityPolicy::Reject);
+        fill(&store).await;
+        let result = store.insert("new".to_string(), shared::paste(unix_now(), None)).await;
+        assert!(matches!(result, Err(StoreError::Full)));
+        assert_eq!(ids(&store).await, ["first", "second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn evict_oldest_drops_earliest_created() {
+        let store = capped(3, usize::MAX, CapacityPolicy::EvictOldest);
+        fill(&store).await;
+        store.insert("new".to_string(), shared::paste(unix_now(), None)).await.unwrap();
+        assert_eq!(ids(&store).await, ["second", "third", "new"]);
+    }
+
+    #[tokio::test]
+    async fn evict_lru_drops_least_recently_read() {
+        let store = capped(3, usize::MAX, CapacityPolicy::EvictLru);
+        fill(&store).await;
+        store.insert("new".to_string(), shared::paste(unix_now(), None)).await.unwrap();
+        assert_eq!(ids(&store).await, ["first", "third", "new"]);
+    }
+
+    #[tokio::test]
+    async fn eviction_frees_enough_bytes() {
+        let size = shared::paste(0, None).size();
+        let store = capped(usize::MAX, 3 * size, CapacityPolicy::EvictOldest);
+        fill(&store).await;
+        let large = EncryptedPaste {
+            encrypted_data: vec![0; 2 * size - 12],
+            ..shared::paste(unix_now(), None)
+        };
+        store.insert("new".to_string(), large).await.unwrap();
+        assert_eq!(ids(&store).await, ["third", "new"]);
+        assert_eq!(store.stats().await.unwrap().total_bytes, 3 * size);
+    }
+
+    #[tokio::test]
+    async fn oversized_paste_is_refused_without_evicting() {
+        let size = shared::paste(0, None).size();
+        let store = capped(usize::MAX, 3 * size, CapacityPolicy::EvictOldest);
+        fill(&store).await;
+        let oversized = EncryptedPaste {
+            encrypted_data: vec![0; 3 * size],
+            ..shared::paste(unix_now(), None)
+        };
+        assert!(matches!(store.insert("new".to_string(), oversized).await, Err(StoreError::Full)));
+        assert_eq!(ids(&store).await, ["first", "second", "third"]);
+    }
+}
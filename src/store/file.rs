@@ -0,0 +1,383 @@
+use super::{
+    unix_now, AccessFailure, AccessGuard, AccessLimits, EncryptedBlob, EncryptedPaste, EvictionOrder, MemoryStore,
+    PasteStore, StoreError, StoreStats,
+};
+use crate::logging;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::{info, warn};
+
+const FILE_FORMAT_VERSION: u32 = 1;
+const FILE_EXTENSION: &str = "json";
+
+#[derive(Serialize)]
+struct PasteFileRef<'a> {
+    version: u32,
+    paste: &'a EncryptedPaste,
+}
+
+#[derive(Deserialize)]
+struct PasteFile {
+    version: u32,
+    paste: EncryptedPaste,
+}
+
+/// Persistent backend: pastes are served from memory and mirrored to one JSON
+/// file per paste in the data directory, which is reloaded on startup.
+///
+/// Files only ever contain the client-side ciphertext, nonce and timestamps;
+/// the decryption key never reaches the server, so nothing readable is written.
+pub struct FileStore {
+    dir: PathBuf,
+    memory: MemoryStore,
+    /// Serializes view and access-counter updates so the file on disk always
+    /// matches memory.
+    view_lock: Mutex<()>,
+}
+
+impl FileStore {
+    pub async fn open(dir: &Path) -> Result<Self, StoreError> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| backend_error("create data directory", dir, e))?;
+        let store = Self {
+            dir: dir.to_path_buf(),
+            memory: MemoryStore::new(),
+            view_lock: Mutex::new(()),
+        };
+        store.load().await?;
+        Ok(store)
+    }
+
+    /// Reloads every unexpired paste from disk, discarding expired, partial or
+    /// unreadable files.
+    async fn load(&self) -> Result<(), StoreError> {
+        let now = unix_now();
+        let (mut loaded, mut expired) = (0usize, 0usize);
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .map_err(|e| backend_error("read data directory", &self.dir, e))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| backend_error("read data directory", &self.dir, e))?
+        {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(FILE_EXTENSION) {
+                if path.to_string_lossy().ends_with(".tmp") {
+                    // Named `<id>.json.tmp`, so only the ID part is logged, and redacted.
+                    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                    let id = name.split('.').next().unwrap_or_default();
+                    warn!("Removing incomplete paste file for {}", logging::paste_id(id));
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()).filter(|id| is_valid_id(id)) else {
+                // Not a valid paste ID, so the name cannot reveal one.
+                warn!("Ignoring unexpected file in data directory: {:?}", path);
+                continue;
+            };
+            let paste = match read_paste_file(&path).await {
+                Ok(paste) => paste,
+                Err(e) => {
+                    warn!("Skipping unreadable paste file for {}: {}", logging::paste_id(id), e);
+                    continue;
+                }
+            };
+            if paste.is_expired(now) {
+                expired += 1;
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+            self.memory.insert(id.to_string(), paste).await?;
+            loaded += 1;
+        }
+        info!(
+            "Loaded {} pastes from {:?} ({} expired pastes discarded)",
+            loaded, self.dir, expired
+        );
+        Ok(())
+    }
+
+    fn paste_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, FILE_EXTENSION))
+    }
+
+    /// Writes the paste to a temporary file and renames it into place so a
+    /// crash never leaves a truncated paste behind.
+    async fn write_paste_file(&self, id: &str, paste: &EncryptedPaste) -> Result<(), StoreError> {
+        let path = self.paste_path(id);
+        let tmp_path = self.dir.join(format!("{}.{}.tmp", id, FILE_EXTENSION));
+        let contents = serde_json::to_vec(&PasteFileRef {
+            version: FILE_FORMAT_VERSION,
+            paste,
+        })
+        .map_err(|e| StoreError::Backend(format!("failed to serialize paste: {}", e)))?;
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(&tmp_path)
+            .await
+            .map_err(|e| backend_error("create paste file in", &self.dir, e))?;
+        file.write_all(&contents)
+            .await
+            .map_err(|e| backend_error("write paste file in", &self.dir, e))?;
+        file.sync_all()
+            .await
+            .map_err(|e| backend_error("sync paste file in", &self.dir, e))?;
+        drop(file);
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| backend_error("rename paste file in", &self.dir, e))
+    }
+
+    async fn remove_paste_file(&self, id: &str) {
+        let path = self.paste_path(id);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove paste file for {}: {}", logging::paste_id(id), e),
+        }
+    }
+}
+
+#[async_trait]
+impl PasteStore for FileStore {
+    async fn insert(&self, id: String, paste: EncryptedPaste) -> Result<(), StoreError> {
+        if !is_valid_id(&id) {
+            return Err(StoreError::Backend(format!("refusing to persist invalid paste id {:?}", id)));
+        }
+        self.memory.insert(id.clone(), paste.clone()).await?;
+        if let Err(e) = self.write_paste_file(&id, &paste).await {
+            self.memory.delete(&id).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        self.memory.get(id).await
+    }
+
+    async fn take_view(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        let _guard = self.view_lock.lock().await;
+        let paste = self.memory.take_view(id).await?;
+        match paste.as_ref().and_then(|paste| paste.remaining_views) {
+            Some(0) => self.remove_paste_file(id).await,
+            Some(_) => {
+                if let Some(paste) = &paste {
+                    self.write_paste_file(id, paste).await?;
+                }
+            }
+            None => {}
+        }
+        Ok(paste)
+    }
+
+    async fn get_chunk(&self, id: &str, index: usize) -> Result<Option<EncryptedBlob>, StoreError> {
+        self.memory.get_chunk(id, index).await
+    }
+
+    async fn access_guard(&self, id: &str) -> Result<Option<AccessGuard>, StoreError> {
+        self.memory.access_guard(id).await
+    }
+
+    async fn record_failed_access(
+        &self,
+        id: &str,
+        now: u64,
+        limits: AccessLimits,
+    ) -> Result<Option<AccessFailure>, StoreError> {
+        let _guard = self.view_lock.lock().await;
+        let failure = self.memory.record_failed_access(id, now, limits).await?;
+        match failure {
+            Some(AccessFailure::Burned) => self.remove_paste_file(id).await,
+            Some(_) => {
+                if let Some(paste) = self.memory.peek(id).await {
+                    self.write_paste_file(id, &paste).await?;
+                }
+            }
+            None => {}
+        }
+        Ok(failure)
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let existed = self.memory.delete(id).await?;
+        if existed {
+            self.remove_paste_file(id).await;
+        }
+        Ok(existed)
+    }
+
+    async fn delete_expired(&self, now: u64) -> Result<usize, StoreError> {
+        let expired = self.memory.remove_expired(now).await;
+        for id in &expired {
+            self.remove_paste_file(id).await;
+        }
+        Ok(expired.len())
+    }
+
+    async fn stats(&self) -> Result<StoreStats, StoreError> {
+        self.memory.stats().await
+    }
+
+    async fn evict_one(&self, order: EvictionOrder) -> Result<Option<(String, usize)>, StoreError> {
+        let evicted = self.memory.evict_one(order).await?;
+        if let Some((id, _)) = &evicted {
+            self.remove_paste_file(id).await;
+        }
+        Ok(evicted)
+    }
+}
+
+// --- Helpers ---
+/// Paste IDs double as file names, so only plain alphanumeric IDs are accepted.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+async fn read_paste_file(path: &Path) -> Result<EncryptedPaste, String> {
+    let contents = tokio::fs::read(path).await.map_err(|e| e.to_string())?;
+    let file: PasteFile = serde_json::from_slice(&contents).map_err(|e| e.to_string())?;
+    if file.version != FILE_FORMAT_VERSION {
+        return Err(format!("unsupported file format version {}", file.version));
+    }
+    Ok(file.paste)
+}
+
+fn backend_error(action: &str, path: &Path, e: std::io::Error) -> StoreError {
+    StoreError::Backend(format!("failed to {} {:?}: {}", action, path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests as shared;
+    use super::*;
+    use std::sync::Arc;
+
+    /// A fresh, empty data directory per test.
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rsdrop-file-store-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn last_view_burns_paste() {
+        let dir = data_dir("last-view");
+        shared::last_view_burns_paste(&FileStore::open(&dir).await.unwrap()).await;
+        assert_eq!(file_names(&dir), ["unlimited.json"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_takes_share_views() {
+        let dir = data_dir("concurrent-takes");
+        shared::concurrent_takes_share_views(Arc::new(FileStore::open(&dir).await.unwrap())).await;
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_lock_out() {
+        shared::wrong_tokens_lock_out(&FileStore::open(&data_dir("lockout")).await.unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_burn() {
+        let dir = data_dir("burn");
+        shared::wrong_tokens_burn(&FileStore::open(&dir).await.unwrap()).await;
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn stats_follow_insert_delete_and_expiry() {
+        let dir = data_dir("stats");
+        shared::stats_follow_insert_delete_and_expiry(&FileStore::open(&dir).await.unwrap()).await;
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn eviction_follows_order() {
+        let dir = data_dir("eviction");
+        shared::eviction_follows_order(&FileStore::open(&dir).await.unwrap()).await;
+        assert!(file_names(&dir).is_empty());
+    }
+
+    #[tokio::test]
+    async fn pastes_survive_a_restart() {
+        let dir = data_dir("restart");
+        let now = unix_now();
+        let bundle = shared::bundle_paste();
+        {
+            let store = FileStore::open(&dir).await.unwrap();
+            store.insert("views".to_string(), shared::paste(now, Some(3))).await.unwrap();
+            store.insert("guarded".to_string(), shared::guarded_paste()).await.unwrap();
+            store.insert("bundle".to_string(), bundle.clone()).await.unwrap();
+            store.take_view("views").await.unwrap();
+            store.record_failed_access("guarded", now, shared::LOCKOUT).await.unwrap();
+        }
+
+        let store = FileStore::open(&dir).await.unwrap();
+        let stats = store.stats().await.unwrap();
+        assert_eq!(stats.paste_count, 3);
+        assert_eq!(stats.total_bytes, 2 * shared::paste(now, None).size() + 16 + bundle.size());
+        assert_eq!(store.get("views").await.unwrap().unwrap().remaining_views, Some(2));
+        assert_eq!(store.access_guard("guarded").await.unwrap().unwrap().failed_attempts, 1);
+        let reloaded = store.get("bundle").await.unwrap().unwrap();
+        assert_eq!(reloaded.chunk_count(), 2);
+        assert_eq!(reloaded.items.len(), 2);
+        assert_eq!(store.get_chunk("bundle", 1).await.unwrap().unwrap().encrypted_data, vec![4; 50]);
+    }
+
+    #[tokio::test]
+    async fn reload_drops_expired_and_incomplete_files() {
+        let dir = data_dir("reload-cleanup");
+        {
+            let store = FileStore::open(&dir).await.unwrap();
+            let expired = EncryptedPaste {
+                expires_at: unix_now() - 1,
+                ..shared::paste(unix_now(), None)
+            };
+            store.insert("expired".to_string(), expired).await.unwrap();
+            store.insert("live".to_string(), shared::paste(unix_now(), None)).await.unwrap();
+        }
+        std::fs::write(dir.join("partial.json.tmp"), b"{\"version\":").unwrap();
+        std::fs::write(dir.join("broken.json"), b"not json").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"left alone").unwrap();
+
+        let store = FileStore::open(&dir).await.unwrap();
+        assert_eq!(store.stats().await.unwrap().paste_count, 1);
+        assert!(store.get("live").await.unwrap().is_some());
+        assert!(store.get("expired").await.unwrap().is_none());
+        // Unreadable files are skipped but kept for inspection.
+        assert_eq!(file_names(&dir), ["broken.json", "live.json", "notes.txt"]);
+    }
+
+    #[tokio::test]
+    async fn invalid_ids_are_not_persisted() {
+        let dir = data_dir("invalid-id");
+        let store = FileStore::open(&dir).await.unwrap();
+        let result = store.insert("../escape".to_string(), shared::paste(unix_now(), None)).await;
+        assert!(matches!(result, Err(StoreError::Backend(_))));
+        assert_eq!(store.stats().await.unwrap().paste_count, 0);
+        assert!(file_names(&dir).is_empty());
+    }
+}
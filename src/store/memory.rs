@@ -0,0 +1,222 @@
+use super::{
+    unix_now, AccessFailure, AccessGuard, AccessLimits, EncryptedBlob, EncryptedPaste, EvictionOrder, PasteStore,
+    StoreError, StoreStats,
+};
+use crate::logging;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// Default backend: pastes live in a `HashMap` and vanish on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    pastes: RwLock<HashMap<String, EncryptedPaste>>,
+    /// Sum of `EncryptedPaste::size` over `pastes`, so `stats` stays O(1) for
+    /// the capacity check on every insert. Only changed under the write lock.
+    total_bytes: AtomicUsize,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes expired pastes and returns their IDs, so wrapping backends can
+    /// clean up their own copies.
+    pub async fn remove_expired(&self, now: u64) -> Vec<String> {
+        let mut pastes = self.pastes.write().await;
+        let expired: Vec<String> = pastes
+            .iter()
+            .filter(|(_, paste)| paste.is_expired(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            info!("Deleting expired paste with id: {}", logging::paste_id(id));
+            if let Some(paste) = pastes.remove(id) {
+                self.release(&paste);
+            }
+        }
+        expired
+    }
+
+    /// Subtracts a removed paste from the byte total. Call with the write lock held.
+    fn release(&self, paste: &EncryptedPaste) {
+        self.total_bytes.fetch_sub(paste.size(), Ordering::Relaxed);
+    }
+
+    /// Returns a copy of an unexpired paste without recording a read.
+    pub async fn peek(&self, id: &str) -> Option<EncryptedPaste> {
+        let now = unix_now();
+        self.pastes.read().await.get(id).filter(|paste| !paste.is_expired(now)).cloned()
+    }
+}
+
+#[async_trait]
+impl PasteStore for MemoryStore {
+    async fn insert(&self, id: String, paste: EncryptedPaste) -> Result<(), StoreError> {
+        let mut pastes = self.pastes.write().await;
+        if pastes.contains_key(&id) {
+            return Err(StoreError::Conflict);
+        }
+        self.total_bytes.fetch_add(paste.size(), Ordering::Relaxed);
+        pastes.insert(id, paste);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        let now = unix_now();
+        let mut pastes = self.pastes.write().await;
+        Ok(pastes.get_mut(id).filter(|paste| !paste.is_expired(now)).map(|paste| {
+            paste.last_read_at = now;
+            paste.clone()
+        }))
+    }
+
+    async fn take_view(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        let now = unix_now();
+        let mut pastes = self.pastes.write().await;
+        let Some(paste) = pastes.get_mut(id).filter(|paste| !paste.is_expired(now)) else {
+            return Ok(None);
+        };
+        paste.last_read_at = now;
+        if let Some(views) = paste.remaining_views.as_mut() {
+            *views = views.saturating_sub(1);
+            if *views == 0 {
+                info!("Paste {} reached its view limit and was deleted", logging::paste_id(id));
+                let removed = pastes.remove(id);
+                removed.iter().for_each(|paste| self.release(paste));
+                return Ok(removed);
+            }
+        }
+        Ok(Some(paste.clone()))
+    }
+
+    async fn get_chunk(&self, id: &str, index: usize) -> Result<Option<EncryptedBlob>, StoreError> {
+        let now = unix_now();
+        let pastes = self.pastes.read().await;
+        Ok(pastes
+            .get(id)
+            .filter(|paste| !paste.is_expired(now))
+            .and_then(|paste| paste.chunks.get(index).cloned()))
+    }
+
+    async fn access_guard(&self, id: &str) -> Result<Option<AccessGuard>, StoreError> {
+        let now = unix_now();
+        let pastes = self.pastes.read().await;
+        Ok(pastes
+            .get(id)
+            .filter(|paste| !paste.is_expired(now))
+            .and_then(|paste| paste.access_guard.clone()))
+    }
+
+    async fn record_failed_access(
+        &self,
+        id: &str,
+        now: u64,
+        limits: AccessLimits,
+    ) -> Result<Option<AccessFailure>, StoreError> {
+        let mut pastes = self.pastes.write().await;
+        let Some(guard) = pastes
+            .get_mut(id)
+            .filter(|paste| !paste.is_expired(now))
+            .and_then(|paste| paste.access_guard.as_mut())
+        else {
+            return Ok(None);
+        };
+        let failure = guard.record_failure(now, limits);
+        if failure == AccessFailure::Burned {
+            info!("Paste {} was deleted after too many wrong access tokens", logging::paste_id(id));
+            if let Some(paste) = pastes.remove(id) {
+                self.release(&paste);
+            }
+        }
+        Ok(Some(failure))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let removed = self.pastes.write().await.remove(id);
+        removed.iter().for_each(|paste| self.release(paste));
+        Ok(removed.is_some())
+    }
+
+    async fn delete_expired(&self, now: u64) -> Result<usize, StoreError> {
+        Ok(self.remove_expired(now).await.len())
+    }
+
+    async fn stats(&self) -> Result<StoreStats, StoreError> {
+        let pastes = self.pastes.read().await;
+        Ok(StoreStats {
+            paste_count: pastes.len(),
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+        })
+    }
+
+    async fn evict_one(&self, order: EvictionOrder) -> Result<Option<(String, usize)>, StoreError> {
+        let mut pastes = self.pastes.write().await;
+        let victim = pastes
+            .iter()
+            .min_by_key(|(_, paste)| paste.eviction_key(order))
+            .map(|(id, _)| id.clone());
+        Ok(victim.and_then(|id| {
+            pastes.remove(&id).map(|paste| {
+                self.release(&paste);
+                (id, paste.size())
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests as shared;
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn last_view_burns_paste() {
+        shared::last_view_burns_paste(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_takes_share_views() {
+        shared::concurrent_takes_share_views(Arc::new(MemoryStore::new())).await;
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_lock_out() {
+        shared::wrong_tokens_lock_out(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_burn() {
+        shared::wrong_tokens_burn(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn stats_follow_insert_delete_and_expiry() {
+        shared::stats_follow_insert_delete_and_expiry(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn eviction_follows_order() {
+        shared::eviction_follows_order(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn expired_pastes_are_hidden_before_the_sweep() {
+        let store = MemoryStore::new();
+        let expired = EncryptedPaste {
+            expires_at: unix_now() - 1,
+            access_guard: Some(AccessGuard::new(vec![3; 32])),
+            ..shared::paste(unix_now(), Some(1))
+        };
+        store.insert("expired".to_string(), expired).await.unwrap();
+        assert!(store.get("expired").await.unwrap().is_none());
+        assert!(store.take_view("expired").await.unwrap().is_none());
+        assert!(store.access_guard("expired").await.unwrap().is_none());
+        assert!(store.peek("expired").await.is_none());
+        assert_eq!(store.remove_expired(unix_now()).await, ["expired"]);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::envelope::Cipher;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod capped;
+mod file;
+mod memory;
+mod sqlite;
+
+pub use capped::{CapacityLimits, CapacityPolicy, CappedStore};
+pub use file::FileStore;
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+// --- Data Structures ---
+/// One independently encrypted piece of ciphertext with its own nonce.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EncryptedBlob {
+    #[serde(with = "b64")]
+    pub encrypted_data: Vec<u8>,
+    #[serde(with = "b64")]
+    pub nonce: Vec<u8>,
+}
+
+impl EncryptedBlob {
+    pub fn size(&self) -> usize {
+        self.encrypted_data.len() + self.nonce.len()
+    }
+}
+
+/// One entry of a bundle: encrypted content plus optional encrypted file
+/// metadata, each with its own nonce.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BundleItem {
+    pub content: EncryptedBlob,
+    #[serde(default)]
+    pub metadata: Option<EncryptedBlob>,
+}
+
+impl BundleItem {
+    pub fn size(&self) -> usize {
+        self.content.size() + self.metadata.as_ref().map_or(0, EncryptedBlob::size)
+    }
+}
+
+/// A stored paste. Timestamps are wall-clock UNIX seconds so they remain
+/// meaningful after a restart and can be serialized by persistent backends.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptedPaste {
+    #[serde(with = "b64")]
+    pub encrypted_data: Vec<u8>,
+    #[serde(with = "b64")]
+    pub nonce: Vec<u8>,
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// Last successful read, used for least-recently-read eviction.
+    #[serde(default)]
+    pub last_read_at: u64,
+    /// Views left before the paste is deleted; `None` means unlimited.
+    #[serde(default)]
+    pub remaining_views: Option<u32>,
+    /// SHA-256 of the creator's deletion token. The token itself is never stored.
+    #[serde(default, with = "b64::option")]
+    pub deletion_token_hash: Option<Vec<u8>>,
+    /// Ciphertext of pastes created through the chunked upload API, in order.
+    /// Such pastes leave `encrypted_data` and `nonce` empty. Shared so that
+    /// cloning a large paste stays cheap.
+    #[serde(default)]
+    pub chunks: Arc<Vec<EncryptedBlob>>,
+    /// Chunks a backend left in storage instead of loading into `chunks`;
+    /// they are served one at a time through [`PasteStore::get_chunk`].
+    #[serde(skip)]
+    pub unloaded_chunks: usize,
+    /// Encrypted file name, MIME type and size for file attachments. Opaque to
+    /// the server, like the content itself.
+    #[serde(default)]
+    pub metadata: Option<EncryptedBlob>,
+    /// Further items sharing this paste's link. A paste with items is a
+    /// bundle; its own ciphertext is the bundle's note.
+    #[serde(default)]
+    pub items: Arc<Vec<BundleItem>>,
+    /// Client-side passphrase KDF parameters and salt for password-protected
+    /// pastes. Opaque to the server; the passphrase itself never leaves the browser.
+    #[serde(default, with = "b64::option")]
+    pub password_kdf: Option<Vec<u8>>,
+    /// Server-side guess limiting for password-protected pastes.
+    #[serde(default)]
+    pub access_guard: Option<AccessGuard>,
+    /// Encryption scheme from the upload's envelope; `None` for pastes stored
+    /// before envelopes existed. Read it through [`EncryptedPaste::cipher`].
+    #[serde(default)]
+    pub cipher: Option<Cipher>,
+}
+
+impl EncryptedPaste {
+    /// Number of bytes this paste accounts for in storage. Only loaded chunks
+    /// count, so call it on pastes about to be stored, not ones read back.
+    pub fn size(&self) -> usize {
+        self.encrypted_data.len()
+            + self.nonce.len()
+            + self.chunks.iter().map(EncryptedBlob::size).sum::<usize>()
+            + self.metadata.as_ref().map_or(0, EncryptedBlob::size)
+            + self.items.iter().map(BundleItem::size).sum::<usize>()
+            + self.password_kdf.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Number of chunks of a paste created through the chunked upload API.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len() + self.unloaded_chunks
+    }
+
+    /// Scheme that produced this paste's ciphertext.
+    pub fn cipher(&self) -> Cipher {
+        self.cipher.unwrap_or_else(|| Cipher::legacy(self.password_kdf.is_some()))
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Sort key used when choosing which paste to evict first.
+    pub fn eviction_key(&self, order: EvictionOrder) -> u64 {
+        match order {
+            EvictionOrder::Oldest => self.created_at,
+            EvictionOrder::LeastRecentlyRead => self.last_read_at.max(self.created_at),
+        }
+    }
+}
+
+/// Guards a paste behind an access token derived from its key and passphrase.
+/// Only the token's SHA-256 is stored, alongside a count of wrong tokens.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AccessGuard {
+    #[serde(with = "b64")]
+    pub token_hash: Vec<u8>,
+    #[serde(default)]
+    pub failed_attempts: u32,
+    /// UNIX seconds until which every access attempt is refused.
+    #[serde(default)]
+    pub locked_until: u64,
+}
+
+impl AccessGuard {
+    pub fn new(token_hash: Vec<u8>) -> Self {
+        Self {
+            token_hash,
+            failed_attempts: 0,
+            locked_until: 0,
+        }
+    }
+
+    pub fn is_locked(&self, now: u64) -> bool {
+        now < self.locked_until
+    }
+
+    /// Counts one wrong token and applies `limits`. A [`AccessFailure::Burned`]
+    /// result means the caller must delete the paste.
+    pub fn record_failure(&mut self, now: u64, limits: AccessLimits) -> AccessFailure {
+        self.failed_attempts = self.failed_attempts.saturating_add(1);
+        if self.failed_attempts < limits.max_attempts {
+            return AccessFailure::AttemptsLeft(limits.max_attempts - self.failed_attempts);
+        }
+        match limits.policy {
+            AccessFailurePolicy::Burn => AccessFailure::Burned,
+            AccessFailurePolicy::Lockout => {
+                self.failed_attempts = 0;
+                self.locked_until = now + limits.lockout_secs;
+                AccessFailure::LockedUntil(self.locked_until)
+            }
+        }
+    }
+}
+
+/// What to do once a guarded paste has seen too many wrong access tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AccessFailurePolicy {
+    /// Refuse all access for a while, then allow a fresh round of attempts (default).
+    Lockout,
+    /// Delete the paste.
+    Burn,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AccessLimits {
+    pub max_attempts: u32,
+    pub policy: AccessFailurePolicy,
+    pub lockout_secs: u64,
+}
+
+/// Result of recording a wrong access token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessFailure {
+    AttemptsLeft(u32),
+    LockedUntil(u64),
+    Burned,
+}
+
+/// Which paste an evicting backend should drop first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionOrder {
+    Oldest,
+    LeastRecentlyRead,
+}
+
+/// Current wall-clock time as UNIX seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Serde helper storing byte buffers as standard base64 strings.
+mod b64 {
+    use base64::{engine::general_purpose::STANDARD as base64_engine, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64_engine.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64_engine.decode(encoded).map_err(serde::de::Error::custom)
+    }
+
+    pub mod option {
+        use super::base64_engine;
+        use base64::Engine as _;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+            match bytes {
+                Some(bytes) => serializer.serialize_some(&base64_engine.encode(bytes)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|encoded| base64_engine.decode(encoded).map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StoreStats {
+    pub paste_count: usize,
+    pub total_bytes: usize,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// A paste with the same ID already exists.
+    Conflict,
+    /// Storing the paste would exceed the configured capacity limits.
+    Full,
+    /// The backend failed to complete the operation.
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Conflict => write!(f, "paste ID already exists"),
+            StoreError::Full => write!(f, "storage capacity exhausted"),
+            StoreError::Backend(msg) => write!(f, "storage backend error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Storage backend for encrypted pastes.
+///
+/// Implementations only ever see ciphertext and nonces; they must be safe to
+/// share across request handlers and the background cleanup task.
+#[async_trait]
+pub trait PasteStore: Send + Sync {
+    /// Stores a new paste. Fails with [`StoreError::Conflict`] if the ID is taken.
+    async fn insert(&self, id: String, paste: EncryptedPaste) -> Result<(), StoreError>;
+
+    /// Returns a copy of the paste, if present, and records the read time.
+    async fn get(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError>;
+
+    /// Like [`PasteStore::get`], but also counts a view: if the paste has a view
+    /// limit, `remaining_views` is decremented and the paste is deleted once it
+    /// reaches zero. Must be atomic so concurrent readers can never both obtain
+    /// the last view.
+    async fn take_view(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError>;
+
+    /// Returns one chunk of a chunked paste without touching the rest.
+    async fn get_chunk(&self, id: &str, index: usize) -> Result<Option<EncryptedBlob>, StoreError>;
+
+    /// Returns the access guard of an unexpired paste, or `None` if the paste
+    /// does not exist or is not guarded. Does not count as a read.
+    async fn access_guard(&self, id: &str) -> Result<Option<AccessGuard>, StoreError>;
+
+    /// Records a wrong access token against a guarded paste, deleting it if
+    /// `limits` say so. Must be atomic so concurrent guesses are all counted.
+    /// Returns `None` if the paste does not exist or is not guarded.
+    async fn record_failed_access(
+        &self,
+        id: &str,
+        now: u64,
+        limits: AccessLimits,
+    ) -> Result<Option<AccessFailure>, StoreError>;
+
+    /// Removes a paste. Returns `true` if it existed.
+    async fn delete(&self, id: &str) -> Result<bool, StoreError>;
+
+    /// Removes every paste whose expiry is at or before `now` (UNIX seconds)
+    /// and returns how many were deleted.
+    async fn delete_expired(&self, now: u64) -> Result<usize, StoreError>;
+
+    /// Current number of pastes and bytes held by the backend. `CappedStore`
+    /// calls this on every insert, so backends keep running totals rather
+    /// than scanning their pastes.
+    async fn stats(&self) -> Result<StoreStats, StoreError>;
+
+    /// Removes the first paste according to `order` to free space. Returns the
+    /// evicted ID and its size, or `None` if the store is empty.
+    async fn evict_one(&self, order: EvictionOrder) -> Result<Option<(String, usize)>, StoreError>;
+}
+
+// --- Backend Selection ---
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StoreBackend {
+    /// Keep pastes in RAM only (default).
+    Memory,
+    /// Keep pastes in RAM and mirror them to one file per paste in `--data-dir`.
+    File,
+    /// Keep pastes in a single SQLite database file in `--data-dir`.
+    Sqlite,
+}
+
+impl StoreBackend {
+    pub async fn open(self, data_dir: Option<&Path>) -> Result<Box<dyn PasteStore>, StoreError> {
+        match self {
+            StoreBackend::Memory => Ok(Box::new(MemoryStore::new())),
+            StoreBackend::File => {
+                let dir = data_dir
+                    .ok_or_else(|| StoreError::Backend("the file store requires --data-dir".to_string()))?;
+                Ok(Box::new(FileStore::open(dir).await?))
+            }
+            StoreBackend::Sqlite => {
+                let dir = data_dir
+                    .ok_or_else(|| StoreError::Backend("the sqlite store requires --data-dir".to_string()))?;
+                Ok(Box::new(SqliteStore::open(dir).await?))
+            }
+        }
+    }
+}
+
+// --- Shared Backend Tests ---
+/// Behaviour every backend must agree on. Each backend's own tests run these
+/// checks against a fresh instance.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) const LOCKOUT: AccessLimits = AccessLimits {
+        max_attempts: 3,
+        policy: AccessFailurePolicy::Lockout,
+        lockout_secs: 60,
+    };
+
+    pub(super) const BURN: AccessLimits = AccessLimits {
+        max_attempts: 2,
+        policy: AccessFailurePolicy::Burn,
+        lockout_secs: 60,
+    };
+
+    /// An unexpired 22-byte paste created at `created_at`.
+    pub(super) fn paste(created_at: u64, remaining_views: Option<u32>) -> EncryptedPaste {
+        EncryptedPaste {
+            encrypted_data: vec![0; 10],
+            nonce: vec![1; 12],
+            created_at,
+            expires_at: unix_now() + 3600,
+            last_read_at: created_at,
+            remaining_views,
+            deletion_token_hash: None,
+            chunks: Default::default(),
+            unloaded_chunks: 0,
+            metadata: None,
+            items: Default::default(),
+            password_kdf: None,
+            access_guard: None,
+            cipher: None,
+        }
+    }
+
+    pub(super) fn guarded_paste() -> EncryptedPaste {
+        EncryptedPaste {
+            password_kdf: Some(vec![2; 16]),
+            access_guard: Some(AccessGuard::new(vec![3; 32])),
+            ..paste(unix_now(), None)
+        }
+    }
+
+    /// A paste with every kind of child data, so size accounting covers all of it.
+    pub(super) fn bundle_paste() -> EncryptedPaste {
+        let blob = |len| EncryptedBlob {
+            encrypted_data: vec![4; len],
+            nonce: vec![5; 12],
+        };
+        EncryptedPaste {
+            chunks: Arc::new(vec![blob(100), blob(50)]),
+            metadata: Some(blob(30)),
+            items: Arc::new(vec![
+                BundleItem {
+                    content: blob(40),
+                    metadata: Some(blob(20)),
+                },
+                BundleItem {
+                    content: blob(10),
+                    metadata: None,
+                },
+            ]),
+            password_kdf: Some(vec![2; 16]),
+            ..paste(unix_now(), None)
+        }
+    }
+
+    async fn assert_stats(store: &dyn PasteStore, paste_count: usize, total_bytes: usize) {
+        let stats = store.stats().await.unwrap();
+        assert_eq!((stats.paste_count, stats.total_bytes), (paste_count, total_bytes));
+    }
+
+    pub(super) async fn last_view_burns_paste(store: &dyn PasteStore) {
+        store.insert("views".to_string(), paste(unix_now(), Some(2))).await.unwrap();
+        let first = store.take_view("views").await.unwrap().unwrap();
+        assert_eq!(first.remaining_views, Some(1));
+        assert!(store.get("views").await.unwrap().is_some());
+
+        let last = store.take_view("views").await.unwrap().unwrap();
+        assert_eq!(last.remaining_views, Some(0));
+        assert!(store.get("views").await.unwrap().is_none());
+        assert!(store.take_view("views").await.unwrap().is_none());
+        assert_stats(store, 0, 0).await;
+
+        store.insert("unlimited".to_string(), paste(unix_now(), None)).await.unwrap();
+        for _ in 0..3 {
+            assert!(store.take_view("unlimited").await.unwrap().is_some());
+        }
+    }
+
+    pub(super) async fn concurrent_takes_share_views(store: Arc<dyn PasteStore>) {
+        store.insert("shared".to_string(), paste(unix_now(), Some(3))).await.unwrap();
+        let readers: Vec<_> = (0..16)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move { store.take_view("shared").await.unwrap() })
+            })
+            .collect();
+        let mut views_left = Vec::new();
+        for reader in readers {
+            if let Some(paste) = reader.await.unwrap() {
+                views_left.push(paste.remaining_views.unwrap());
+            }
+        }
+        views_left.sort_unstable();
+        assert_eq!(views_left, [0, 1, 2]);
+        assert!(store.get("shared").await.unwrap().is_none());
+    }
+
+    pub(super) async fn wrong_tokens_lock_out(store: &dyn PasteStore) {
+        let now = unix_now();
+        store.insert("guarded".to_string(), guarded_paste()).await.unwrap();
+        store.insert("open".to_string(), paste(now, None)).await.unwrap();
+        assert_eq!(store.record_failed_access("open", now, LOCKOUT).await.unwrap(), None);
+        assert_eq!(store.record_failed_access("missing", now, LOCKOUT).await.unwrap(), None);
+
+        let attempt = || store.record_failed_access("guarded", now, LOCKOUT);
+        assert_eq!(attempt().await.unwrap(), Some(AccessFailure::AttemptsLeft(2)));
+        assert_eq!(attempt().await.unwrap(), Some(AccessFailure::AttemptsLeft(1)));
+        assert_eq!(attempt().await.unwrap(), Some(AccessFailure::LockedUntil(now + 60)));
+
+        let guard = store.access_guard("guarded").await.unwrap().unwrap();
+        assert!(guard.is_locked(now + 59));
+        assert!(!guard.is_locked(now + 60));
+        assert_eq!(guard.failed_attempts, 0);
+        assert!(store.get("guarded").await.unwrap().is_some());
+
+        // Once the lockout ends, a fresh round of attempts starts.
+        let later = store.record_failed_access("guarded", now + 60, LOCKOUT).await.unwrap();
+        assert_eq!(later, Some(AccessFailure::AttemptsLeft(2)));
+    }
+
+    pub(super) async fn wrong_tokens_burn(store: &dyn PasteStore) {
+        let now = unix_now();
+        store.insert("guarded".to_string(), guarded_paste()).await.unwrap();
+        let attempt = || store.record_failed_access("guarded", now, BURN);
+        assert_eq!(attempt().await.unwrap(), Some(AccessFailure::AttemptsLeft(1)));
+        assert_eq!(attempt().await.unwrap(), Some(AccessFailure::Burned));
+
+        assert!(store.get("guarded").await.unwrap().is_none());
+        assert!(store.access_guard("guarded").await.unwrap().is_none());
+        assert_eq!(attempt().await.unwrap(), None);
+        assert_stats(store, 0, 0).await;
+    }
+
+    pub(super) async fn stats_follow_insert_delete_and_expiry(store: &dyn PasteStore) {
+        let small = paste(unix_now(), None);
+        let bundle = bundle_paste();
+        let expired = EncryptedPaste {
+            expires_at: unix_now() - 1,
+            ..bundle_paste()
+        };
+        let (small_size, bundle_size) = (small.size(), bundle.size());
+        assert_stats(store, 0, 0).await;
+
+        store.insert("small".to_string(), small).await.unwrap();
+        store.insert("bundle".to_string(), bundle).await.unwrap();
+        store.insert("expired".to_string(), expired).await.unwrap();
+        assert_stats(store, 3, small_size + 2 * bundle_size).await;
+        assert!(matches!(
+            store.insert("small".to_string(), paste(unix_now(), None)).await,
+            Err(StoreError::Conflict)
+        ));
+        assert_stats(store, 3, small_size + 2 * bundle_size).await;
+
+        assert_eq!(store.delete_expired(unix_now()).await.unwrap(), 1);
+        assert_stats(store, 2, small_size + bundle_size).await;
+
+        assert!(store.delete("small").await.unwrap());
+        assert!(!store.delete("small").await.unwrap());
+        assert_stats(store, 1, bundle_size).await;
+
+        assert!(store.delete("bundle").await.unwrap());
+        assert_stats(store, 0, 0).await;
+    }
+
+    pub(super) async fn eviction_follows_order(store: &dyn PasteStore) {
+        for (id, created_at) in [("first", 100), ("second", 200), ("third", 300)] {
+            store.insert(id.to_string(), paste(created_at, None)).await.unwrap();
+        }
+        store.get("first").await.unwrap();
+
+        let size = paste(0, None).size();
+        let evict = |order| store.evict_one(order);
+        assert_eq!(evict(EvictionOrder::LeastRecentlyRead).await.unwrap(), Some(("second".to_string(), size)));
+        assert_eq!(evict(EvictionOrder::Oldest).await.unwrap(), Some(("first".to_string(), size)));
+        assert_eq!(evict(EvictionOrder::Oldest).await.unwrap(), Some(("third".to_string(), size)));
+        assert_eq!(evict(EvictionOrder::Oldest).await.unwrap(), None);
+        assert_stats(store, 0, 0).await;
+    }
+}
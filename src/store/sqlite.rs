@@ -0,0 +1,714 @@
+use super::{
+    unix_now, AccessFailure, AccessGuard, AccessLimits, BundleItem, EncryptedBlob, EncryptedPaste, EvictionOrder,
+    PasteStore, StoreError, StoreStats,
+};
+use crate::{
+    envelope::{Algorithm, Cipher, Kdf},
+    logging,
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing::info;
+
+pub const DATABASE_FILE: &str = "rsdrop.sqlite3";
+
+/// Schema migrations, applied in order. The index of the last applied entry
+/// plus one is recorded in `PRAGMA user_version`; never edit a released entry,
+/// only append new ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE pastes (
+        id             TEXT PRIMARY KEY NOT NULL,
+        encrypted_data BLOB NOT NULL,
+        nonce          BLOB NOT NULL,
+        created_at     INTEGER NOT NULL,
+        expires_at     INTEGER NOT NULL
+    ) STRICT;
+    CREATE INDEX pastes_expires_at ON pastes (expires_at);",
+    // 2: eviction bookkeeping
+    "ALTER TABLE pastes ADD COLUMN last_read_at INTEGER NOT NULL DEFAULT 0;
+    UPDATE pastes SET last_read_at = created_at;
+    CREATE INDEX pastes_created_at ON pastes (created_at);
+    CREATE INDEX pastes_last_read_at ON pastes (last_read_at);",
+    // 3: view-limited pastes
+    "ALTER TABLE pastes ADD COLUMN remaining_views INTEGER;",
+    // 4: creator deletion tokens
+    "ALTER TABLE pastes ADD COLUMN deletion_token_hash BLOB;",
+    // 5: chunked uploads
+    "CREATE TABLE paste_chunks (
+        paste_id       TEXT NOT NULL REFERENCES pastes (id) ON DELETE CASCADE,
+        idx            INTEGER NOT NULL,
+        encrypted_data BLOB NOT NULL,
+        nonce          BLOB NOT NULL,
+        PRIMARY KEY (paste_id, idx)
+    ) STRICT;",
+    // 6: encrypted file metadata
+    "ALTER TABLE pastes ADD COLUMN metadata BLOB;
+    ALTER TABLE pastes ADD COLUMN metadata_nonce BLOB;",
+    // 7: bundles
+    "CREATE TABLE paste_items (
+        paste_id       TEXT NOT NULL REFERENCES pastes (id) ON DELETE CASCADE,
+        idx            INTEGER NOT NULL,
+        encrypted_data BLOB NOT NULL,
+        nonce          BLOB NOT NULL,
+        metadata       BLOB,
+        metadata_nonce BLOB,
+        PRIMARY KEY (paste_id, idx)
+    ) STRICT;",
+    // 8: password-protected pastes
+    "ALTER TABLE pastes ADD COLUMN password_kdf BLOB;",
+    // 9: server-side access guards
+    "ALTER TABLE pastes ADD COLUMN access_token_hash BLOB;
+    ALTER TABLE pastes ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE pastes ADD COLUMN locked_until INTEGER NOT NULL DEFAULT 0;",
+    // 10: ciphertext envelope scheme; NULL for pastes stored before envelopes
+    "ALTER TABLE pastes ADD COLUMN cipher_algorithm INTEGER;
+    ALTER TABLE pastes ADD COLUMN cipher_kdf INTEGER;",
+    // 11: running usage totals, so the capacity check on every insert reads one row
+    // instead of scanning every paste; cascaded chunk and item deletes fire their triggers too
+    "CREATE TABLE store_usage (
+        id          INTEGER PRIMARY KEY CHECK (id = 0),
+        paste_count INTEGER NOT NULL,
+        total_bytes INTEGER NOT NULL
+    ) STRICT;
+    INSERT INTO store_usage (id, paste_count, total_bytes) SELECT 0,
+        (SELECT COUNT(*) FROM pastes),
+        (SELECT COALESCE(SUM(LENGTH(encrypted_data) + LENGTH(nonce)
+            + COALESCE(LENGTH(metadata) + LENGTH(metadata_nonce), 0)
+            + COALESCE(LENGTH(password_kdf), 0)), 0) FROM pastes)
+        + (SELECT COALESCE(SUM(LENGTH(encrypted_data) + LENGTH(nonce)), 0) FROM paste_chunks)
+        + (SELECT COALESCE(SUM(LENGTH(encrypted_data) + LENGTH(nonce)
+            + COALESCE(LENGTH(metadata) + LENGTH(metadata_nonce), 0)), 0) FROM paste_items);
+    CREATE TRIGGER pastes_usage_insert AFTER INSERT ON pastes BEGIN
+        UPDATE store_usage SET paste_count = paste_count + 1, total_bytes = total_bytes
+            + LENGTH(NEW.encrypted_data) + LENGTH(NEW.nonce)
+            + COALESCE(LENGTH(NEW.metadata) + LENGTH(NEW.metadata_nonce), 0) + COALESCE(LENGTH(NEW.password_kdf), 0);
+    END;
+    CREATE TRIGGER pastes_usage_delete AFTER DELETE ON pastes BEGIN
+        UPDATE store_usage SET paste_count = paste_count - 1, total_bytes = total_bytes
+            - LENGTH(OLD.encrypted_data) - LENGTH(OLD.nonce)
+            - COALESCE(LENGTH(OLD.metadata) + LENGTH(OLD.metadata_nonce), 0) - COALESCE(LENGTH(OLD.password_kdf), 0);
+    END;
+    CREATE TRIGGER paste_chunks_usage_insert AFTER INSERT ON paste_chunks BEGIN
+        UPDATE store_usage SET total_bytes = total_bytes + LENGTH(NEW.encrypted_data) + LENGTH(NEW.nonce);
+    END;
+    CREATE TRIGGER paste_chunks_usage_delete AFTER DELETE ON paste_chunks BEGIN
+        UPDATE store_usage SET total_bytes = total_bytes - LENGTH(OLD.encrypted_data) - LENGTH(OLD.nonce);
+    END;
+    CREATE TRIGGER paste_items_usage_insert AFTER INSERT ON paste_items BEGIN
+        UPDATE store_usage SET total_bytes = total_bytes + LENGTH(NEW.encrypted_data) + LENGTH(NEW.nonce)
+            + COALESCE(LENGTH(NEW.metadata) + LENGTH(NEW.metadata_nonce), 0);
+    END;
+    CREATE TRIGGER paste_items_usage_delete AFTER DELETE ON paste_items BEGIN
+        UPDATE store_usage SET total_bytes = total_bytes - LENGTH(OLD.encrypted_data) - LENGTH(OLD.nonce)
+            - COALESCE(LENGTH(OLD.metadata) + LENGTH(OLD.metadata_nonce), 0);
+    END;",
+];
+
+/// Columns read back into an [`EncryptedPaste`], in `paste_from_row` order.
+const PASTE_COLUMNS: &str = "encrypted_data, nonce, created_at, expires_at, last_read_at, remaining_views,
+    deletion_token_hash, metadata, metadata_nonce, password_kdf, access_token_hash, failed_attempts, locked_until,
+    cipher_algorithm, cipher_kdf";
+
+/// Columns read back into an [`AccessGuard`] by `access_guard_from_row`.
+const ACCESS_GUARD_COLUMNS: &str = "access_token_hash, failed_attempts, locked_until";
+
+/// Single-file SQLite backend. Expiry sweeps are a single indexed `DELETE`.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub async fn open(dir: &Path) -> Result<Self, StoreError> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| StoreError::Backend(format!("failed to create data directory {:?}: {}", dir, e)))?;
+        let path = dir.join(DATABASE_FILE);
+        let conn = tokio::task::spawn_blocking(move || -> rusqlite::Result<Connection> {
+            let mut conn = Connection::open(&path)?;
+            conn.execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 PRAGMA synchronous = NORMAL;
+                 PRAGMA secure_delete = ON;
+                 PRAGMA foreign_keys = ON;",
+            )?;
+            migrate(&mut conn)?;
+            info!("Opened SQLite paste store at {:?}", path);
+            Ok(conn)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(format!("database task failed: {}", e)))?
+        .map_err(sqlite_error)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs a blocking database operation off the async runtime.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| StoreError::Backend("database lock poisoned".to_string()))?;
+            f(&mut conn).map_err(sqlite_error)
+        })
+        .await
+        .map_err(|e| StoreError::Backend(format!("database task failed: {}", e)))?
+    }
+}
+
+/// Brings the schema up to date inside a single transaction.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if current > MIGRATIONS.len() {
+        return Err(rusqlite::Error::InvalidParameterName(format!(
+            "database schema version {} is newer than this build supports ({})",
+            current,
+            MIGRATIONS.len()
+        )));
+    }
+    if current == MIGRATIONS.len() {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        info!("Applying SQLite schema migration {}", index + 1);
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()
+}
+
+fn paste_from_row(row: &Row<'_>) -> rusqlite::Result<EncryptedPaste> {
+    Ok(EncryptedPaste {
+        encrypted_data: row.get(0)?,
+        nonce: row.get(1)?,
+        created_at: row.get(2)?,
+        expires_at: row.get(3)?,
+        last_read_at: row.get(4)?,
+        remaining_views: row.get(5)?,
+        deletion_token_hash: row.get(6)?,
+        chunks: Default::default(),
+        unloaded_chunks: 0,
+        metadata: metadata_from_row(row, 7)?,
+        items: Default::default(),
+        password_kdf: row.get(9)?,
+        access_guard: access_guard_from_row(row, 10)?,
+        cipher: cipher_from_row(row, 13)?,
+    })
+}
+
+/// Reads the envelope's algorithm and KDF IDs, stored as two nullable columns.
+fn cipher_from_row(row: &Row<'_>, first: usize) -> rusqlite::Result<Option<Cipher>> {
+    let (Some(algorithm), Some(kdf)) = (row.get::<_, Option<u8>>(first)?, row.get::<_, Option<u8>>(first + 1)?) else {
+        return Ok(None);
+    };
+    let unknown = |index, id| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            rusqlite::types::Type::Integer,
+            format!("unknown envelope ID {}", id).into(),
+        )
+    };
+    Ok(Some(Cipher {
+        algorithm: Algorithm::from_id(algorithm).ok_or_else(|| unknown(first, algorithm))?,
+        kdf: Kdf::from_id(kdf).ok_or_else(|| unknown(first + 1, kdf))?,
+    }))
+}
+
+fn access_guard_from_row(row: &Row<'_>, first: usize) -> rusqlite::Result<Option<AccessGuard>> {
+    let token_hash: Option<Vec<u8>> = row.get(first)?;
+    token_hash
+        .map(|token_hash| {
+            Ok(AccessGuard {
+                token_hash,
+                failed_attempts: row.get(first + 1)?,
+                locked_until: row.get(first + 2)?,
+            })
+        })
+        .transpose()
+}
+
+/// Reads an optional metadata blob stored as two nullable columns.
+fn metadata_from_row(row: &Row<'_>, first: usize) -> rusqlite::Result<Option<EncryptedBlob>> {
+    Ok(match (row.get(first)?, row.get(first + 1)?) {
+        (Some(encrypted_data), Some(nonce)) => Some(EncryptedBlob { encrypted_data, nonce }),
+        _ => None,
+    })
+}
+
+fn count_chunks(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+    let mut stmt = conn.prepare_cached("SELECT COUNT(*) FROM paste_chunks WHERE paste_id = ?1")?;
+    stmt.query_row(params![id], |row| row.get(0))
+}
+
+fn load_items(conn: &Connection, id: &str) -> rusqlite::Result<Vec<BundleItem>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT encrypted_data, nonce, metadata, metadata_nonce FROM paste_items WHERE paste_id = ?1 ORDER BY idx",
+    )?;
+    let items = stmt.query_map(params![id], |row| {
+        Ok(BundleItem {
+            content: EncryptedBlob {
+                encrypted_data: row.get(0)?,
+                nonce: row.get(1)?,
+            },
+            metadata: metadata_from_row(row, 2)?,
+        })
+    })?;
+    items.collect()
+}
+
+fn read_access_guard(conn: &Connection, id: &str, now: u64) -> rusqlite::Result<Option<AccessGuard>> {
+    let guard = conn
+        .query_row(
+            &format!("SELECT {} FROM pastes WHERE id = ?1 AND expires_at > ?2", ACCESS_GUARD_COLUMNS),
+            params![id, now],
+            |row| access_guard_from_row(row, 0),
+        )
+        .optional()?;
+    Ok(guard.flatten())
+}
+
+/// Fills in the child rows of a paste read from the `pastes` table. Chunks
+/// can add up to a whole upload, so only their number is read; `get_chunk`
+/// serves each one.
+fn load_children(conn: &Connection, id: &str, mut paste: EncryptedPaste) -> rusqlite::Result<EncryptedPaste> {
+    paste.unloaded_chunks = count_chunks(conn, id)?;
+    paste.items = load_items(conn, id)?.into();
+    Ok(paste)
+}
+
+/// Size of a paste including its chunks, matching [`EncryptedPaste::size`].
+const PASTE_SIZE_SQL: &str = "LENGTH(encrypted_data) + LENGTH(nonce)
+    + COALESCE(LENGTH(metadata) + LENGTH(metadata_nonce), 0) + COALESCE(LENGTH(password_kdf), 0)
+    + (SELECT COALESCE(SUM(LENGTH(c.encrypted_data) + LENGTH(c.nonce)), 0) FROM paste_chunks c WHERE c.paste_id = id)
+    + (SELECT COALESCE(SUM(LENGTH(i.encrypted_data) + LENGTH(i.nonce)
+        + COALESCE(LENGTH(i.metadata) + LENGTH(i.metadata_nonce), 0)), 0) FROM paste_items i WHERE i.paste_id = id)";
+
+fn sqlite_error(e: rusqlite::Error) -> StoreError {
+    match e {
+        rusqlite::Error::SqliteFailure(ref err, _) if err.code == ErrorCode::ConstraintViolation => {
+            StoreError::Conflict
+        }
+        e => StoreError::Backend(e.to_string()),
+    }
+}
+
+#[async_trait]
+impl PasteStore for SqliteStore {
+    async fn insert(&self, id: String, paste: EncryptedPaste) -> Result<(), StoreError> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                &format!(
+                    "INSERT INTO pastes (id, {}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                    PASTE_COLUMNS
+                ),
+                params![
+                    id,
+                    paste.encrypted_data,
+                    paste.nonce,
+                    paste.created_at,
+                    paste.expires_at,
+                    paste.last_read_at,
+                    paste.remaining_views,
+                    paste.deletion_token_hash,
+                    paste.metadata.as_ref().map(|metadata| &metadata.encrypted_data),
+                    paste.metadata.as_ref().map(|metadata| &metadata.nonce),
+                    paste.password_kdf,
+                    paste.access_guard.as_ref().map(|guard| &guard.token_hash),
+                    paste.access_guard.as_ref().map_or(0, |guard| guard.failed_attempts),
+                    paste.access_guard.as_ref().map_or(0, |guard| guard.locked_until),
+                    paste.cipher.map(|cipher| cipher.algorithm.id()),
+                    paste.cipher.map(|cipher| cipher.kdf.id())
+                ],
+            )?;
+            for (index, chunk) in paste.chunks.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO paste_chunks (paste_id, idx, encrypted_data, nonce) VALUES (?1, ?2, ?3, ?4)",
+                    params![id, index, chunk.encrypted_data, chunk.nonce],
+                )?;
+            }
+            for (index, item) in paste.items.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO paste_items (paste_id, idx, encrypted_data, nonce, metadata, metadata_nonce)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        id,
+                        index,
+                        item.content.encrypted_data,
+                        item.content.nonce,
+                        item.metadata.as_ref().map(|metadata| &metadata.encrypted_data),
+                        item.metadata.as_ref().map(|metadata| &metadata.nonce)
+                    ],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        let id = id.to_string();
+        let now = unix_now();
+        self.with_conn(move |conn| {
+            let paste = conn
+                .query_row(
+                    &format!(
+                        "UPDATE pastes SET last_read_at = ?2 WHERE id = ?1 AND expires_at > ?2 RETURNING {}",
+                        PASTE_COLUMNS
+                    ),
+                    params![id, now],
+                    paste_from_row,
+                )
+                .optional()?;
+            paste.map(|paste| load_children(conn, &id, paste)).transpose()
+        })
+        .await
+    }
+
+    async fn take_view(&self, id: &str) -> Result<Option<EncryptedPaste>, StoreError> {
+        let id = id.to_string();
+        let now = unix_now();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let paste = tx
+                .query_row(
+                    &format!(
+                        "UPDATE pastes SET last_read_at = ?2, remaining_views = remaining_views - 1
+                         WHERE id = ?1 AND expires_at > ?2 RETURNING {}",
+                        PASTE_COLUMNS
+                    ),
+                    params![id, now],
+                    paste_from_row,
+                )
+                .optional()?
+                .map(|paste| load_children(&tx, &id, paste))
+                .transpose()?;
+            if paste.as_ref().and_then(|paste| paste.remaining_views) == Some(0) {
+                tx.execute("DELETE FROM pastes WHERE id = ?1", params![id])?;
+                info!("Paste {} reached its view limit and was deleted", logging::paste_id(&id));
+            }
+            tx.commit()?;
+            Ok(paste)
+        })
+        .await
+    }
+
+    async fn get_chunk(&self, id: &str, index: usize) -> Result<Option<EncryptedBlob>, StoreError> {
+        let id = id.to_string();
+        let now = unix_now();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT c.encrypted_data, c.nonce FROM paste_chunks c JOIN pastes p ON p.id = c.paste_id
+                 WHERE c.paste_id = ?1 AND c.idx = ?2 AND p.expires_at > ?3",
+                params![id, index, now],
+                |row| {
+                    Ok(EncryptedBlob {
+                        encrypted_data: row.get(0)?,
+                        nonce: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn access_guard(&self, id: &str) -> Result<Option<AccessGuard>, StoreError> {
+        let id = id.to_string();
+        let now = unix_now();
+        self.with_conn(move |conn| read_access_guard(conn, &id, now)).await
+    }
+
+    async fn record_failed_access(
+        &self,
+        id: &str,
+        now: u64,
+        limits: AccessLimits,
+    ) -> Result<Option<AccessFailure>, StoreError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let Some(mut guard) = read_access_guard(&tx, &id, now)? else {
+                return Ok(None);
+            };
+            let failure = guard.record_failure(now, limits);
+            if failure == AccessFailure::Burned {
+                tx.execute("DELETE FROM pastes WHERE id = ?1", params![id])?;
+                info!("Paste {} was deleted after too many wrong access tokens", logging::paste_id(&id));
+            } else {
+                tx.execute(
+                    "UPDATE pastes SET failed_attempts = ?2, locked_until = ?3 WHERE id = ?1",
+                    params![id, guard.failed_attempts, guard.locked_until],
+                )?;
+            }
+            tx.commit()?;
+            Ok(Some(failure))
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, StoreError> {
+        let id = id.to_string();
+        self.with_conn(move |conn| conn.execute("DELETE FROM pastes WHERE id = ?1", params![id]).map(|n| n > 0))
+            .await
+    }
+
+    async fn delete_expired(&self, now: u64) -> Result<usize, StoreError> {
+        self.with_conn(move |conn| conn.execute("DELETE FROM pastes WHERE expires_at <= ?1", params![now]))
+            .await
+    }
+
+    async fn stats(&self) -> Result<StoreStats, StoreError> {
+        self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT paste_count, total_bytes FROM store_usage WHERE id = 0",
+                [],
+                |row| {
+                    Ok(StoreStats {
+                        paste_count: row.get(0)?,
+                        total_bytes: row.get(1)?,
+                    })
+                },
+            )
+        })
+        .await
+    }
+
+    async fn evict_one(&self, order: EvictionOrder) -> Result<Option<(String, usize)>, StoreError> {
+        let order_column = match order {
+            EvictionOrder::Oldest => "created_at",
+            EvictionOrder::LeastRecentlyRead => "last_read_at",
+        };
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let victim: Option<(String, usize)> = tx
+                .query_row(
+                    &format!(
+                        "SELECT id, {} FROM pastes ORDER BY {} LIMIT 1",
+                        PASTE_SIZE_SQL, order_column
+                    ),
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            if let Some((id, _)) = &victim {
+                tx.execute("DELETE FROM pastes WHERE id = ?1", params![id])?;
+            }
+            tx.commit()?;
+            Ok(victim)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests as shared;
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A fresh, empty data directory per test.
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rsdrop-sqlite-store-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn open(name: &str) -> SqliteStore {
+        SqliteStore::open(&data_dir(name)).await.unwrap()
+    }
+
+    /// An in-memory database with only the first `version` migrations applied.
+    fn schema_at(version: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..version] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", version).unwrap();
+        conn
+    }
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap()
+    }
+
+    fn usage(conn: &Connection) -> (usize, usize) {
+        conn.query_row("SELECT paste_count, total_bytes FROM store_usage WHERE id = 0", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap()
+    }
+
+    fn recount(conn: &Connection) -> (usize, usize) {
+        conn.query_row(
+            &format!("SELECT COUNT(*), COALESCE(SUM({}), 0) FROM pastes", PASTE_SIZE_SQL),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    /// Checks the trigger-maintained totals against a full scan of the rows.
+    async fn assert_usage_matches_rows(store: &SqliteStore) {
+        let (usage, recount) = store.with_conn(|conn| Ok((usage(conn), recount(conn)))).await.unwrap();
+        assert_eq!(usage, recount);
+        let stats = store.stats().await.unwrap();
+        assert_eq!((stats.paste_count, stats.total_bytes), usage);
+    }
+
+    #[test]
+    fn migrate_creates_fresh_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        assert_eq!(usage(&conn), (0, 0));
+
+        // Running it again on an up-to-date schema is a no-op.
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn migrate_upgrades_older_schema_and_keeps_pastes() {
+        let mut conn = schema_at(4);
+        conn.execute(
+            "INSERT INTO pastes (id, encrypted_data, nonce, created_at, expires_at, last_read_at, remaining_views)
+             VALUES ('old', x'00010203', x'0405', 100, ?1, 100, 2)",
+            params![u64::MAX / 2],
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let paste = conn
+            .query_row(&format!("SELECT {} FROM pastes WHERE id = 'old'", PASTE_COLUMNS), [], paste_from_row)
+            .unwrap();
+        assert_eq!(paste.encrypted_data, [0, 1, 2, 3]);
+        assert_eq!(paste.remaining_views, Some(2));
+        assert!(paste.access_guard.is_none());
+        assert!(paste.cipher.is_none());
+        assert_eq!(usage(&conn), (1, 6));
+    }
+
+    #[test]
+    fn migrate_backfills_usage_from_child_rows() {
+        let mut conn = schema_at(MIGRATIONS.len() - 1);
+        conn.execute_batch(
+            "INSERT INTO pastes (id, encrypted_data, nonce, created_at, expires_at, metadata, metadata_nonce,
+                password_kdf)
+             VALUES ('bundle', x'00', x'0102', 1, 2, x'03', x'04', x'0506'),
+                    ('plain', x'0708', x'09', 1, 2, NULL, NULL, NULL);
+             INSERT INTO paste_chunks (paste_id, idx, encrypted_data, nonce) VALUES ('bundle', 0, x'0a0b0c', x'0d');
+             INSERT INTO paste_items (paste_id, idx, encrypted_data, nonce, metadata, metadata_nonce)
+             VALUES ('bundle', 0, x'0e', x'0f', x'10', x'11');",
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(usage(&conn), (2, 18));
+        assert_eq!(usage(&conn), recount(&conn));
+    }
+
+    #[test]
+    fn migrate_refuses_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        let error = migrate(&mut conn).unwrap_err().to_string();
+        assert!(error.contains("newer than this build supports"), "{}", error);
+        assert_eq!(user_version(&conn), MIGRATIONS.len() + 1);
+        let tables: usize = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[tokio::test]
+    async fn usage_totals_match_rows() {
+        let store = open("usage").await;
+        store.insert("bundle".to_string(), shared::bundle_paste()).await.unwrap();
+        store.insert("guarded".to_string(), shared::guarded_paste()).await.unwrap();
+        store.insert("views".to_string(), shared::paste(unix_now(), Some(1))).await.unwrap();
+        let expired = EncryptedPaste {
+            expires_at: unix_now() - 1,
+            ..shared::bundle_paste()
+        };
+        store.insert("expired".to_string(), expired).await.unwrap();
+        assert_usage_matches_rows(&store).await;
+        assert_eq!(store.stats().await.unwrap().paste_count, 4);
+
+        assert_eq!(store.delete_expired(unix_now()).await.unwrap(), 1);
+        assert_usage_matches_rows(&store).await;
+
+        store.take_view("views").await.unwrap().unwrap();
+        assert_usage_matches_rows(&store).await;
+
+        store.record_failed_access("guarded", unix_now(), shared::BURN).await.unwrap();
+        store.record_failed_access("guarded", unix_now(), shared::BURN).await.unwrap();
+        assert_usage_matches_rows(&store).await;
+
+        assert!(store.delete("bundle").await.unwrap());
+        assert_usage_matches_rows(&store).await;
+        assert_eq!(store.stats().await.unwrap().total_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn last_view_burns_paste() {
+        shared::last_view_burns_paste(&open("last-view").await).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_takes_share_views() {
+        shared::concurrent_takes_share_views(Arc::new(open("concurrent-takes").await)).await;
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_lock_out() {
+        shared::wrong_tokens_lock_out(&open("lockout").await).await;
+    }
+
+    #[tokio::test]
+    async fn wrong_tokens_burn() {
+        shared::wrong_tokens_burn(&open("burn").await).await;
+    }
+
+    #[tokio::test]
+    async fn stats_follow_insert_delete_and_expiry() {
+        shared::stats_follow_insert_delete_and_expiry(&open("stats").await).await;
+    }
+
+    #[tokio::test]
+    async fn eviction_follows_order() {
+        shared::eviction_follows_order(&open("eviction").await).await;
+    }
+
+    #[tokio::test]
+    async fn pastes_survive_a_restart() {
+        let dir = data_dir("restart");
+        let bundle = shared::bundle_paste();
+        {
+            let store = SqliteStore::open(&dir).await.unwrap();
+            store.insert("bundle".to_string(), bundle.clone()).await.unwrap();
+            store.insert("views".to_string(), shared::paste(unix_now(), Some(3))).await.unwrap();
+            store.take_view("views").await.unwrap();
+        }
+
+        let store = SqliteStore::open(&dir).await.unwrap();
+        assert_eq!(store.get("views").await.unwrap().unwrap().remaining_views, Some(2));
+        let reloaded = store.get("bundle").await.unwrap().unwrap();
+        assert_eq!(reloaded.chunk_count(), 2);
+        assert_eq!(reloaded.items.len(), 2);
+        assert_eq!(store.get_chunk("bundle", 0).await.unwrap().unwrap().encrypted_data, vec![4; 100]);
+        assert_eq!(store.stats().await.unwrap().total_bytes, bundle.size() + shared::paste(0, None).size());
+        assert_usage_matches_rows(&store).await;
+    }
+}
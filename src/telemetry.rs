@@ -0,0 +1,89 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::{global, propagation::Extractor};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+    Resource,
+};
+use std::time::Duration;
+use tracing::{field, info_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// --- Configuration Constants ---
+pub const DEFAULT_SERVICE_NAME: &str = "rsdrop";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// --- Export ---
+// Only spans are exported, never log events, and every instrumented function
+// skips its arguments. Span fields are limited to route patterns, methods,
+// status codes and counts, so no paste ID or payload reaches the collector.
+
+/// Builds the OTLP/HTTP exporter for `endpoint`, the collector's full traces
+/// URL (e.g. `http://localhost:4318/v1/traces`), and makes the W3C
+/// `traceparent` header of incoming requests the parent of their spans.
+pub fn init_tracer(
+    endpoint: &str,
+    service_name: &str,
+    sample_ratio: f64,
+) -> Result<SdkTracerProvider, opentelemetry_otlp::ExporterBuildError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .with_timeout(EXPORT_TIMEOUT)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(sample_ratio))))
+        .with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+        .build();
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(provider)
+}
+
+/// Parses `--otlp-sample-ratio`, which must lie between 0 and 1.
+pub fn parse_sample_ratio(value: &str) -> Result<f64, String> {
+    let ratio: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+// --- Middleware ---
+/// Runs the request inside a server span named after its route pattern,
+/// never the path itself, which would contain the paste ID.
+pub async fn request_span(matched_path: Option<MatchedPath>, request: Request, next: Next) -> Response {
+    let route = matched_path.as_ref().map_or("unmatched", MatchedPath::as_str);
+    let span = info_span!(
+        crate::logging::REQUEST_SPAN,
+        otel.name = %format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        http.route = %route,
+        http.response.status_code = field::Empty,
+    );
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(request.headers())));
+    let _ = span.set_parent(parent);
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+    response
+}
@@ -0,0 +1,504 @@
+use crate::{
+    auth::ApiKey, check_proof_of_work, decode_access_guard, decode_metadata, decode_password_kdf, read_body_limited,
+    envelope::{Algorithm, Cipher},
+    store::{unix_now, AccessGuard, EncryptedBlob},
+    proxy::ClientIp,
+    store_new_paste, validate_nonce, validate_paste_options, CreateEncryptedPasteResponse, NewPaste, PasteOptions, PowProof,
+    SharedState, NONCE_HEADER,
+};
+use axum::{
+    body::Body,
+    extract::{Extension, Json, Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine as _};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+// --- Configuration Constants ---
+const UPLOAD_ID_LENGTH: usize = 32; // The upload ID is the only credential for a session
+const UPLOAD_SESSION_TTL_SECS: u64 = 60 * 60; // Unfinished sessions are dropped after 1 hour
+const MAX_UPLOAD_SESSIONS: usize = 64;
+const MAX_UPLOAD_SESSIONS_PER_CLIENT: usize = 4;
+const MAX_UPLOAD_CHUNKS: u32 = 10_000;
+
+// --- Data Structures ---
+/// An upload in progress. Chunks may arrive in any order and may be re-sent,
+/// which is what makes resuming after a dropped connection possible.
+struct UploadSession {
+    expires_in_secs: Option<u64>,
+    cipher: Option<Cipher>,
+    metadata: Option<EncryptedBlob>,
+    password_kdf: Option<Vec<u8>>,
+    access_guard: Option<AccessGuard>,
+    chunks: BTreeMap<u32, EncryptedBlob>,
+    total_bytes: usize,
+    /// The size declared when the session was opened. It is reserved against
+    /// the storage cap until the session ends.
+    max_bytes: usize,
+    client: IpAddr,
+    /// Key that opened the session; the finished paste counts against its quota.
+    api_key: Option<Arc<ApiKey>>,
+    expires_at: u64,
+    /// Set while finalize stores the paste. The chunks are lent to the store
+    /// meanwhile and come back if storing fails, so the client can retry.
+    finalizing: bool,
+}
+
+/// In-progress chunked uploads, keyed by their secret upload ID. Sessions are
+/// kept in memory only; a restart discards them.
+#[derive(Default)]
+pub struct UploadSessions {
+    sessions: Mutex<HashMap<String, UploadSession>>,
+}
+
+impl UploadSessions {
+    pub async fn remove_expired(&self, now: u64) -> usize {
+        let mut sessions = self.sessions.lock().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| session.expires_at > now);
+        before - sessions.len()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InitiateUploadRequest {
+    /// Bytes of ciphertext and nonces the chunks will add up to, at most.
+    total_size: usize,
+    #[serde(default)]
+    expires_in_secs: Option<u64>,
+    /// Algorithm and KDF of every chunk; AES-256-GCM when omitted.
+    #[serde(default)]
+    cipher: Option<Cipher>,
+    /// Encrypted file metadata, as accepted by `/create`.
+    #[serde(default)]
+    encrypted_metadata_b64: Option<String>,
+    #[serde(default)]
+    metadata_nonce_b64: Option<String>,
+    #[serde(default)]
+    password_kdf_b64: Option<String>,
+    #[serde(default)]
+    access_token_b64: Option<String>,
+    /// Solved challenge, as for `/create`. Its size becomes the session's upload limit.
+    #[serde(default)]
+    pow_challenge: Option<String>,
+    #[serde(default)]
+    pow_nonce: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct InitiateUploadResponse {
+    upload_id: String,
+    max_chunk_size: usize,
+    max_chunks: u32,
+    max_upload_size: usize,
+    session_expires_in_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct UploadStatusResponse {
+    received_chunks: Vec<u32>,
+    total_bytes: usize,
+    session_expires_in_secs: u64,
+}
+
+#[derive(Deserialize)]
+pub struct FinalizeUploadRequest {
+    chunk_count: u32,
+}
+
+// --- Route Handlers ---
+pub async fn handle_initiate_upload(
+    State(state): State<SharedState>,
+    ClientIp(client): ClientIp,
+    api_key: Option<Extension<Arc<ApiKey>>>,
+    Json(payload): Json<InitiateUploadRequest>,
+) -> Result<Json<InitiateUploadResponse>, (StatusCode, String)> {
+    let algorithm = payload.cipher.map(|cipher| cipher.algorithm).unwrap_or_default();
+    let options = PasteOptions {
+        expires_in_secs: payload.expires_in_secs,
+        max_views: None,
+    };
+    let template = NewPaste {
+        cipher: payload.cipher,
+        metadata: decode_metadata(
+            payload.encrypted_metadata_b64.as_deref(),
+            payload.metadata_nonce_b64.as_deref(),
+            algorithm,
+        )?,
+        password_kdf: decode_password_kdf(payload.password_kdf_b64.as_deref())?,
+        access_guard: decode_access_guard(payload.access_token_b64.as_deref())?,
+        ..Default::default()
+    };
+    // Reject bad options now rather than after the whole file was uploaded.
+    validate_paste_options(&state.config, &template, &options)?;
+    let api_key = api_key.map(|Extension(key)| key);
+    let max_bytes = payload.total_size;
+    if max_bytes == 0 || max_bytes > state.config.max_upload_bytes {
+        warn!("Rejecting upload session: declared size {} bytes", max_bytes);
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("total_size must be between 1 and {} bytes", state.config.max_upload_bytes),
+        ));
+    }
+
+    let upload_id: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(UPLOAD_ID_LENGTH)
+        .map(char::from)
+        .collect();
+    let now = unix_now();
+    {
+        let mut sessions = state.uploads.sessions.lock().await;
+        sessions.retain(|_, session| session.expires_at > now);
+        if sessions.len() >= MAX_UPLOAD_SESSIONS {
+            warn!("Rejecting upload session: {} sessions already open", sessions.len());
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Too many uploads in progress, please try again later.".to_string()));
+        }
+        if sessions.values().filter(|session| session.client == client).count() >= MAX_UPLOAD_SESSIONS_PER_CLIENT {
+            warn!("Rejecting upload session: client already has {} open", MAX_UPLOAD_SESSIONS_PER_CLIENT);
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("At most {} uploads may be in progress per client", MAX_UPLOAD_SESSIONS_PER_CLIENT),
+            ));
+        }
+        let reserved: usize = sessions.values().map(|session| session.max_bytes).sum();
+        if reserved.saturating_add(max_bytes) > state.config.capacity.max_total_bytes {
+            warn!("Rejecting upload session: open uploads already reserve {} bytes", reserved);
+            return Err((StatusCode::INSUFFICIENT_STORAGE, "Server storage is full, please try again later.".to_string()));
+        }
+        // Redeemed last, so a session refused above does not use up the proof.
+        let proof = PowProof {
+            challenge: payload.pow_challenge.as_deref(),
+            nonce: payload.pow_nonce.as_deref(),
+            size: max_bytes,
+        };
+        check_proof_of_work(&state, api_key.as_deref(), &proof)?;
+        sessions.insert(
+            upload_id.clone(),
+            UploadSession {
+                expires_in_secs: options.expires_in_secs,
+                cipher: template.cipher,
+                metadata: template.metadata,
+                password_kdf: template.password_kdf,
+                access_guard: template.access_guard,
+                chunks: BTreeMap::new(),
+                total_bytes: 0,
+                max_bytes,
+                client,
+                api_key,
+                expires_at: now + UPLOAD_SESSION_TTL_SECS,
+                finalizing: false,
+            },
+        );
+    }
+
+    info!("Started chunked upload session");
+    Ok(Json(InitiateUploadResponse {
+        upload_id,
+        max_chunk_size: state.config.max_paste_bytes,
+        max_chunks: MAX_UPLOAD_CHUNKS,
+        max_upload_size: max_bytes,
+        session_expires_in_secs: UPLOAD_SESSION_TTL_SECS,
+    }))
+}
+
+/// Stores (or replaces) chunk `index`. The body is the raw ciphertext and the
+/// chunk's own nonce, sized for the session's algorithm, is sent
+/// base64-encoded in the `X-Nonce` header.
+pub async fn handle_upload_chunk(
+    State(state): State<SharedState>,
+    Path((upload_id, index)): Path<(String, u32)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<UploadStatusResponse>, (StatusCode, String)> {
+    if index >= MAX_UPLOAD_CHUNKS {
+        return Err((StatusCode::BAD_REQUEST, format!("Chunk index must be below {}", MAX_UPLOAD_CHUNKS)));
+    }
+    let Some(nonce_b64) = headers.get(NONCE_HEADER).and_then(|v| v.to_str().ok()) else {
+        return Err((StatusCode::BAD_REQUEST, format!("Missing {} header", NONCE_HEADER)));
+    };
+    let nonce = base64_engine.decode(nonce_b64).map_err(|e| {
+        warn!("Failed to decode chunk nonce base64: {}", e);
+        (StatusCode::BAD_REQUEST, "Invalid nonce encoding".to_string())
+    })?;
+    // Fail fast on unknown sessions before reading a potentially large body.
+    validate_nonce(session_algorithm(&state, &upload_id).await?, &nonce)?;
+    let encrypted_data = read_body_limited(body, &headers, state.config.max_paste_bytes.saturating_sub(nonce.len())).await?;
+    let chunk = EncryptedBlob { encrypted_data, nonce };
+
+    let now = unix_now();
+    let mut sessions = state.uploads.sessions.lock().await;
+    let pending_bytes: usize = sessions.values().map(|session| session.total_bytes).sum();
+    let session = live_session(&mut sessions, &upload_id, now)?;
+    if session.finalizing {
+        return Err(finalizing_conflict());
+    }
+    let replaced = session.chunks.get(&index).map_or(0, EncryptedBlob::size);
+    let new_total = session.total_bytes - replaced + chunk.size();
+    if new_total > session.max_bytes {
+        warn!("Chunked upload exceeded the maximum upload size");
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "Upload exceeds maximum size limit".to_string()));
+    }
+    if pending_bytes - replaced + chunk.size() > state.config.capacity.max_total_bytes {
+        warn!("Rejecting chunk: pending uploads exceed the storage budget");
+        return Err((StatusCode::INSUFFICIENT_STORAGE, "Server storage is full, please try again later.".to_string()));
+    }
+    session.chunks.insert(index, chunk);
+    session.total_bytes = new_total;
+    Ok(Json(status_response(session, now)))
+}
+
+/// Lists the chunks received so far so a client can resume an interrupted upload.
+pub async fn handle_upload_status(
+    State(state): State<SharedState>,
+    Path(upload_id): Path<String>,
+) -> Result<Json<UploadStatusResponse>, (StatusCode, String)> {
+    session_status(&state, &upload_id).await.map(Json)
+}
+
+/// Turns a complete session into a paste. Chunks `0..chunk_count` must all be
+/// present and no others may exist. The session is only dropped once the
+/// paste is stored; after a refusal such as a full store or a used-up quota,
+/// the client can finalize again without uploading anything.
+pub async fn handle_finalize_upload(
+    State(state): State<SharedState>,
+    Path(upload_id): Path<String>,
+    Json(payload): Json<FinalizeUploadRequest>,
+) -> Result<Json<CreateEncryptedPasteResponse>, (StatusCode, String)> {
+    let now = unix_now();
+    let (new_paste, options, api_key) = {
+        let mut sessions = state.uploads.sessions.lock().await;
+        let session = live_session(&mut sessions, &upload_id, now)?;
+        if session.finalizing {
+            return Err(finalizing_conflict());
+        }
+        let complete = payload.chunk_count > 0
+            && session.chunks.len() == payload.chunk_count as usize
+            && session.chunks.keys().copied().eq(0..payload.chunk_count);
+        if !complete {
+            return Err((
+                StatusCode::CONFLICT,
+                format!(
+                    "Upload incomplete: expected chunks 0..{}, received {} chunk(s)",
+                    payload.chunk_count,
+                    session.chunks.len()
+                ),
+            ));
+        }
+        session.finalizing = true;
+        let new_paste = NewPaste {
+            cipher: session.cipher,
+            chunks: Arc::new(std::mem::take(&mut session.chunks).into_values().collect()),
+            metadata: session.metadata.clone(),
+            password_kdf: session.password_kdf.clone(),
+            access_guard: session.access_guard.clone(),
+            ..Default::default()
+        };
+        let options = PasteOptions {
+            expires_in_secs: session.expires_in_secs,
+            max_views: None,
+        };
+        (new_paste, options, session.api_key.clone())
+    };
+
+    let chunks = new_paste.chunks.clone();
+    let stored = store_new_paste(&state, new_paste, options, api_key.as_deref(), None).await;
+    let mut sessions = state.uploads.sessions.lock().await;
+    match stored {
+        Ok(response) => {
+            sessions.remove(&upload_id);
+            Ok(Json(response))
+        }
+        Err(e) => {
+            // The session may have been aborted or expired in the meantime.
+            if let Some(session) = sessions.get_mut(&upload_id) {
+                session.chunks = (0..).zip(Arc::unwrap_or_clone(chunks)).collect();
+                session.finalizing = false;
+            }
+            Err(e)
+        }
+    }
+}
+
+pub async fn handle_abort_upload(State(state): State<SharedState>, Path(upload_id): Path<String>) -> StatusCode {
+    match state.uploads.sessions.lock().await.remove(&upload_id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+// --- Helpers ---
+fn finalizing_conflict() -> (StatusCode, String) {
+    (StatusCode::CONFLICT, "Upload is being finalized".to_string())
+}
+
+fn live_session<'a>(
+    sessions: &'a mut HashMap<String, UploadSession>,
+    upload_id: &str,
+    now: u64,
+) -> Result<&'a mut UploadSession, (StatusCode, String)> {
+    match sessions.get_mut(upload_id) {
+        Some(session) if session.expires_at > now => Ok(session),
+        _ => Err((StatusCode::NOT_FOUND, "Upload session not found or expired".to_string())),
+    }
+}
+
+async fn session_status(state: &SharedState, upload_id: &str) -> Result<UploadStatusResponse, (StatusCode, String)> {
+    let now = unix_now();
+    let mut sessions = state.uploads.sessions.lock().await;
+    live_session(&mut sessions, upload_id, now).map(|session| status_response(session, now))
+}
+
+async fn session_algorithm(state: &SharedState, upload_id: &str) -> Result<Algorithm, (StatusCode, String)> {
+    let mut sessions = state.uploads.sessions.lock().await;
+    live_session(&mut sessions, upload_id, unix_now())
+        .map(|session| session.cipher.map(|cipher| cipher.algorithm).unwrap_or_default())
+}
+
+fn status_response(session: &UploadSession, now: u64) -> UploadStatusResponse {
+    UploadStatusResponse {
+        received_chunks: session.chunks.keys().copied().collect(),
+        total_bytes: session.total_bytes,
+        session_expires_in_secs: session.expires_at.saturating_sub(now),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_state;
+    use axum::http::{header, HeaderValue};
+    use std::net::Ipv4Addr;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+    const NONCE_B64: &str = "YWJjZGVmZ2hpamts"; // 12 bytes, as AES-256-GCM expects
+
+    async fn initiate(state: &SharedState, client: IpAddr, total_size: usize) -> Result<String, StatusCode> {
+        let request = serde_json::from_value(serde_json::json!({ "total_size": total_size })).unwrap();
+        handle_initiate_upload(State(state.clone()), ClientIp(client), None, Json(request))
+            .await
+            .map(|Json(response)| response.upload_id)
+            .map_err(|(status, _)| status)
+    }
+
+    /// Uploads chunk `index` and returns the indices received so far.
+    async fn put_chunk(state: &SharedState, upload_id: &str, index: u32, data: &[u8]) -> Result<Vec<u32>, StatusCode> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(data.len()));
+        headers.insert(NONCE_HEADER, HeaderValue::from_static(NONCE_B64));
+        let path = Path((upload_id.to_string(), index));
+        handle_upload_chunk(State(state.clone()), path, headers, Body::from(data.to_vec()))
+            .await
+            .map(|Json(status)| status.received_chunks)
+            .map_err(|(status, _)| status)
+    }
+
+    async fn finalize(state: &SharedState, upload_id: &str, chunk_count: u32) -> Result<String, StatusCode> {
+        let path = Path(upload_id.to_string());
+        handle_finalize_upload(State(state.clone()), path, Json(FinalizeUploadRequest { chunk_count }))
+            .await
+            .map(|Json(response)| response.paste_id)
+            .map_err(|(status, _)| status)
+    }
+
+    async fn status(state: &SharedState, upload_id: &str) -> UploadStatusResponse {
+        session_status(state, upload_id).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn resent_chunk_replaces_the_earlier_one() {
+        let state = test_state(&[]);
+        let upload_id = initiate(&state, CLIENT, 1024).await.unwrap();
+        assert_eq!(put_chunk(&state, &upload_id, 1, &[1; 10]).await, Ok(vec![1]));
+        assert_eq!(put_chunk(&state, &upload_id, 0, &[0; 10]).await, Ok(vec![0, 1]));
+        // A client resuming after a dropped connection sends chunk 0 again.
+        assert_eq!(put_chunk(&state, &upload_id, 0, &[2; 20]).await, Ok(vec![0, 1]));
+        assert_eq!(status(&state, &upload_id).await.total_bytes, (20 + 12) + (10 + 12));
+
+        let paste_id = finalize(&state, &upload_id, 2).await.unwrap();
+        let chunk = state.store.get_chunk(&paste_id, 0).await.unwrap().unwrap();
+        assert_eq!(chunk.encrypted_data, [2; 20]);
+        assert!(state.uploads.sessions.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn finalize_requires_exactly_the_declared_chunks() {
+        let state = test_state(&[]);
+        let upload_id = initiate(&state, CLIENT, 1024).await.unwrap();
+        put_chunk(&state, &upload_id, 0, &[0; 10]).await.unwrap();
+        put_chunk(&state, &upload_id, 2, &[2; 10]).await.unwrap();
+
+        assert_eq!(finalize(&state, &upload_id, 3).await, Err(StatusCode::CONFLICT), "chunk 1 is missing");
+        assert_eq!(finalize(&state, &upload_id, 2).await, Err(StatusCode::CONFLICT), "chunk 2 is extra");
+        assert_eq!(finalize(&state, &upload_id, 0).await, Err(StatusCode::CONFLICT));
+        assert_eq!(status(&state, &upload_id).await.received_chunks, [0, 2]);
+
+        put_chunk(&state, &upload_id, 1, &[1; 10]).await.unwrap();
+        let paste_id = finalize(&state, &upload_id, 3).await.unwrap();
+        assert_eq!(state.store.get(&paste_id).await.unwrap().unwrap().chunk_count(), 3);
+        assert_eq!(finalize(&state, &upload_id, 3).await, Err(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn sessions_per_client_are_capped() {
+        let state = test_state(&[]);
+        let mut upload_ids = Vec::new();
+        for _ in 0..MAX_UPLOAD_SESSIONS_PER_CLIENT {
+            upload_ids.push(initiate(&state, CLIENT, 1024).await.unwrap());
+        }
+        assert_eq!(initiate(&state, CLIENT, 1024).await, Err(StatusCode::TOO_MANY_REQUESTS));
+        assert!(initiate(&state, OTHER, 1024).await.is_ok());
+
+        let abort = handle_abort_upload(State(state.clone()), Path(upload_ids.pop().unwrap())).await;
+        assert_eq!(abort, StatusCode::NO_CONTENT);
+        assert!(initiate(&state, CLIENT, 1024).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn failed_store_gives_the_chunks_back() {
+        let state = test_state(&["--max-pastes", "1"]);
+        let first = initiate(&state, CLIENT, 1024).await.unwrap();
+        put_chunk(&state, &first, 0, &[0; 10]).await.unwrap();
+        let stored = finalize(&state, &first, 1).await.unwrap();
+
+        let upload_id = initiate(&state, CLIENT, 1024).await.unwrap();
+        put_chunk(&state, &upload_id, 0, &[0; 10]).await.unwrap();
+        put_chunk(&state, &upload_id, 1, &[1; 10]).await.unwrap();
+        assert_eq!(finalize(&state, &upload_id, 2).await, Err(StatusCode::INSUFFICIENT_STORAGE));
+        let after_failure = status(&state, &upload_id).await;
+        assert_eq!(after_failure.received_chunks, [0, 1]);
+        assert_eq!(after_failure.total_bytes, 2 * (10 + 12));
+
+        // The client retries without uploading anything again.
+        assert!(state.store.delete(&stored).await.unwrap());
+        let paste_id = finalize(&state, &upload_id, 2).await.unwrap();
+        assert_eq!(state.store.get_chunk(&paste_id, 1).await.unwrap().unwrap().encrypted_data, [1; 10]);
+    }
+
+    #[tokio::test]
+    async fn declared_size_is_reserved_until_the_session_ends() {
+        let state = test_state(&["--max-paste-bytes", "1024", "--max-total-bytes", "4096"]);
+        assert_eq!(initiate(&state, CLIENT, 0).await, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        let first = initiate(&state, CLIENT, 3000).await.unwrap();
+        assert_eq!(initiate(&state, OTHER, 2000).await, Err(StatusCode::INSUFFICIENT_STORAGE));
+
+        assert_eq!(handle_abort_upload(State(state.clone()), Path(first)).await, StatusCode::NO_CONTENT);
+        let second = initiate(&state, OTHER, 2000).await.unwrap();
+        assert_eq!(initiate(&state, CLIENT, 3000).await, Err(StatusCode::INSUFFICIENT_STORAGE));
+
+        // Chunks may not outgrow the declared size either.
+        put_chunk(&state, &second, 0, &[0; 1000]).await.unwrap();
+        assert_eq!(put_chunk(&state, &second, 1, &[1; 1000]).await, Err(StatusCode::PAYLOAD_TOO_LARGE));
+        finalize(&state, &second, 1).await.unwrap();
+        assert!(initiate(&state, CLIENT, 3000).await.is_ok());
+    }
+}